@@ -0,0 +1,520 @@
+use std::{borrow::Cow, fs, ops::ControlFlow};
+
+use pest::{
+    iterators::{Pair, Pairs},
+    Parser,
+};
+
+use crate::{
+    diagnostic,
+    hive::{Hive, HiveError, NodeType, PortSchema, Target, Value},
+};
+
+#[derive(Parser)]
+#[grammar = "hive.pest"]
+pub struct HiveParser;
+
+fn number(pair: Pair<Rule>) -> Result<usize, HiveError> {
+    pair.as_str()
+        .parse()
+        .map_err(|_| HiveError::IndexOverflow(pair.as_str().to_string()))
+}
+
+/// Put a name in quotes unless it is a plain identifier, so that it reads back as the very same name.
+pub fn quote(name: &str) -> Cow<'_, str> {
+    if let Ok(mut ident) = HiveParser::parse(Rule::ident, name) {
+        if ident.next().unwrap().as_str() == name {
+            return Cow::Borrowed(name);
+        }
+    }
+    let mut quoted = String::from('"');
+    for c in name.chars() {
+        match c {
+            '"' | '\\' => quoted.extend(['\\', c]),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
+
+/// Node with an optional port in pipe notation, like `osc.out`.
+pub fn end(node: &str, port: Option<&str>) -> String {
+    match port {
+        Some(port) => format!("{}.{port}", quote(node)),
+        None => quote(node).into_owned(),
+    }
+}
+
+/// Declared ports like `{in1, in2}`, or `*` for any port.
+pub fn port_list(ports: Option<&[String]>) -> String {
+    match ports {
+        Some(ports) => format!("{{{}}}", ports.join(", ")),
+        None => "*".to_string(),
+    }
+}
+
+/// Name of a node or text value with quotes and escapes resolved.
+fn name(pair: Pair<'_, Rule>) -> Cow<'_, str> {
+    let Some(quoted) = pair.as_str().strip_prefix('"') else {
+        return Cow::Borrowed(pair.as_str());
+    };
+    let mut name = String::new();
+    let mut chars = quoted[..quoted.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        name.push(match c {
+            '\\' => match chars.next().unwrap() {
+                'n' => '\n',
+                't' => '\t',
+                c => c,
+            },
+            c => c,
+        });
+    }
+    Cow::Owned(name)
+}
+
+/// Neighbors in the notation of the dump, like `b|0, c|1`.
+fn neighbors(hive: &Hive, node: &str, dir: usize) -> Result<String, HiveError> {
+    let neighbors: Vec<_> = hive
+        .neighbors(node, dir)?
+        .into_iter()
+        .map(|(neighbor, edge)| format!("{}|{edge}", quote(neighbor)))
+        .collect();
+    Ok(neighbors.join(", "))
+}
+
+/// Path in pipe notation, like `a > b|0 > c|1`.
+fn route(src: &str, path: Vec<(&str, usize)>) -> String {
+    let mut route = quote(src).into_owned();
+    for (node, edge) in path {
+        route.push_str(&format!(" > {}|{edge}", quote(node)));
+    }
+    route
+}
+
+/// Names joined by spaces, quoted where necessary.
+fn list(names: Vec<&str>) -> String {
+    let names: Vec<_> = names.into_iter().map(quote).collect();
+    names.join(" ")
+}
+
+/// Arguments of a command, past the keyword it starts with.
+fn args(pair: Pair<'_, Rule>) -> Pairs<'_, Rule> {
+    let mut args = pair.into_inner();
+    args.next();
+    args
+}
+
+/// Node name and optional port of a pipe end.
+fn node_port(pair: Pair<'_, Rule>) -> (Cow<'_, str>, Option<&str>) {
+    let mut end = pair.into_inner();
+    let node = name(end.next().unwrap());
+    (node, end.next().map(|port| port.as_str()))
+}
+
+/// Nodes and ports of a single end or of all ends in a group.
+fn ends(pair: Pair<'_, Rule>) -> Vec<(Cow<'_, str>, Option<&str>)> {
+    match pair.as_rule() {
+        Rule::group => pair
+            .into_inner()
+            .filter(|end| end.as_rule() == Rule::end)
+            .map(node_port)
+            .collect(),
+        _ => vec![node_port(pair)],
+    }
+}
+
+/// Node name or `None` for the wildcard.
+fn endpoint(pair: Pair<'_, Rule>) -> Option<Cow<'_, str>> {
+    match pair.as_rule() {
+        Rule::any => None,
+        _ => Some(name(pair)),
+    }
+}
+
+/// Node or edge and key of an attribute.
+fn attr(pair: Pair<Rule>) -> Result<(Target, String), HiveError> {
+    let mut attr = pair.into_inner();
+    let target = attr.next().unwrap();
+    let target = match target.as_rule() {
+        Rule::node => Target::Node(name(target).into_owned()),
+        Rule::edge => Target::Edge(number(target)?),
+        _ => unreachable!(),
+    };
+    Ok((target, attr.next().unwrap().as_str().to_string()))
+}
+
+fn real(pair: Pair<Rule>) -> Result<f64, HiveError> {
+    pair.as_str()
+        .parse()
+        .ok()
+        .filter(|real: &f64| real.is_finite())
+        .ok_or_else(|| HiveError::IndexOverflow(pair.as_str().to_string()))
+}
+
+fn value(pair: Pair<Rule>) -> Result<Value, HiveError> {
+    let value = pair.into_inner().next().unwrap();
+    match value.as_rule() {
+        Rule::real => real(value).map(Value::Number),
+        Rule::text => Ok(Value::Text(name(value).into_owned())),
+        _ => unreachable!(),
+    }
+}
+
+/// Port of a type along with its kind and optional cardinality.
+fn port_schema(pair: Pair<Rule>) -> Result<PortSchema, HiveError> {
+    let mut schema = pair.into_inner();
+    let name = schema.next().unwrap().as_str().to_string();
+    let kind = schema.nth(1).unwrap().as_str().to_string();
+    let max = schema
+        .next()
+        .map(|max| number(max.into_inner().next().unwrap()))
+        .transpose()?;
+    Ok(PortSchema { name, kind, max })
+}
+
+/// Words of the command language, taken from its grammar.
+pub fn keywords() -> Vec<&'static str> {
+    let mut keywords: Vec<_> = include_str!("hive.pest")
+        .split('"')
+        .skip(1)
+        .step_by(2)
+        .filter(|word| word.len() > 1 && word.chars().all(|c| c.is_ascii_alphabetic()))
+        .collect();
+    keywords.sort();
+    keywords.dedup();
+    keywords
+}
+
+/// Execute a script line by line, stopping at the first error or `quit`.
+pub fn run(hive: &mut Hive, script: &str) -> Result<(), HiveError> {
+    for (line, input) in script.lines().enumerate() {
+        let flow =
+            execute(hive, input).map_err(|error| HiveError::Line(line + 1, Box::new(error)))?;
+        if flow.is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Parse and execute a single command line, breaking on `quit`.
+pub fn execute(hive: &mut Hive, input: &str) -> Result<ControlFlow<()>, HiveError> {
+    interpret(hive, input).map_err(|error| diagnostic::suggest(hive, error))
+}
+
+fn interpret(hive: &mut Hive, input: &str) -> Result<ControlFlow<()>, HiveError> {
+    let mut commands = HiveParser::parse(Rule::command, input)
+        .map_err(|error| diagnostic::parse_error(input, error))?;
+    let command = commands.next().unwrap();
+    assert_eq!(command.as_rule(), Rule::command);
+    let mut exprs = command
+        .into_inner()
+        .take_while(|expr| expr.as_rule() != Rule::EOI)
+        .map(|expr| expr.into_inner().next().unwrap())
+        .peekable();
+    // History and file commands travel the undo tree, which needs the changes before them settled
+    let travels = |expr: &Pair<Rule>| matches!(expr.as_rule(), Rule::history | Rule::file);
+    while let Some(expr) = exprs.next() {
+        if travels(&expr) {
+            if expression(hive, expr)?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+            continue;
+        }
+        // The actions of a line apply as a whole, so a failure anywhere undoes all of them
+        let mut group = vec![expr];
+        while let Some(expr) = exprs.next_if(|expr| !travels(expr)) {
+            group.push(expr);
+        }
+        hive.transaction(|hive| {
+            group
+                .into_iter()
+                .try_for_each(|expr| expression(hive, expr).map(drop))
+        })?;
+    }
+    Ok(ControlFlow::Continue(()))
+}
+
+fn expression(hive: &mut Hive, expr: Pair<Rule>) -> Result<ControlFlow<()>, HiveError> {
+    match expr.as_rule() {
+        Rule::action_seq => {
+            expr.into_inner()
+                .try_for_each(|action| execute_action(hive, action))?;
+        }
+        Rule::history => {
+            let history = expr.into_inner().next().unwrap();
+            match history.as_rule() {
+                Rule::quit => return Ok(ControlFlow::Break(())),
+                Rule::clear => hive.clear()?,
+                Rule::pile => {
+                    // Piling more than the whole history just piles the whole history
+                    let n = number(args(history).next().unwrap()).unwrap_or(usize::MAX);
+                    let snapshot = hive.undo.snapshot().saturating_sub(n);
+                    hive.undo.pile(snapshot);
+                }
+                rule @ (Rule::undo | Rule::redo) => {
+                    // Exceeding counts are reported as ignored by the hive itself
+                    let n = args(history)
+                        .map(|u| number(u).unwrap_or(usize::MAX))
+                        .next()
+                        .unwrap_or(1);
+                    match rule {
+                        Rule::undo => hive.undo(n)?,
+                        Rule::redo => hive.redo(n)?,
+                        _ => unreachable!(),
+                    }
+                }
+                // Travel chronologically like vim does, which switches between branches
+                Rule::older => hive.goto(hive.undo.current().saturating_sub(1))?,
+                Rule::newer => hive.goto((hive.undo.current() + 1).min(hive.undo.last()))?,
+                Rule::goto => hive.goto(number(args(history).next().unwrap())?)?,
+                Rule::branches => {
+                    for branch in hive.undo.branches() {
+                        let branch: Vec<_> = branch[1..]
+                            .iter()
+                            .map(|&id| match id == hive.undo.current() {
+                                true => format!("{id}*"),
+                                false => id.to_string(),
+                            })
+                            .collect();
+                        println!("{}", branch.join(" > "));
+                    }
+                }
+                Rule::watch => hive.subscribe(|event| println!("{event}")),
+                Rule::history_list => {
+                    for (id, command) in hive.undo.history()? {
+                        println!("{id}: {command}");
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        Rule::file => {
+            let file = expr.into_inner().next().unwrap();
+            let rule = file.as_rule();
+            let file = args(file);
+            let staged = file.clone().any(|arg| arg.as_rule() == Rule::staged);
+            let path = file.last().unwrap().as_str();
+            match rule {
+                Rule::save => hive.save(path)?,
+                Rule::load => hive.load(path)?,
+                Rule::export => fs::write(path, hive.to_dot(staged))?,
+                _ => unreachable!(),
+            }
+        }
+        Rule::query => {
+            let query = expr.into_inner().next().unwrap();
+            let rule = query.as_rule();
+            let mut query = args(query);
+            let node = query.next().map(name);
+            let node = node.as_deref();
+            match rule {
+                Rule::topo => println!("{}", list(hive.toposort()?)),
+                Rule::eval => match node {
+                    Some(node) => println!("{}", hive.value(node)?),
+                    None => {
+                        for (node, value) in hive.eval()? {
+                            println!("{} = {value}", quote(node));
+                        }
+                    }
+                },
+                Rule::scc => {
+                    for (stage, component) in hive.components() {
+                        println!("{stage}: {}", list(component));
+                    }
+                }
+                Rule::show => {
+                    let node = node.unwrap();
+                    for (dir, dir_str) in [(1, "<"), (0, ">")] {
+                        let neighbors = neighbors(hive, node, dir)?;
+                        println!("{} {dir_str} {neighbors}", quote(node));
+                    }
+                }
+                Rule::incoming => println!("{}", neighbors(hive, node.unwrap(), 1)?),
+                Rule::outgoing => println!("{}", neighbors(hive, node.unwrap(), 0)?),
+                Rule::ports => {
+                    let node = node.unwrap();
+                    for (dir, dir_str) in [(1, "<"), (0, ">")] {
+                        for (port, edges) in hive.ports(node, dir)? {
+                            let edges: Vec<_> = edges
+                                .into_iter()
+                                .map(|(other, port, edge)| format!("{}|{edge}", end(other, port)))
+                                .collect();
+                            println!("{} {dir_str} {}", end(node, port), edges.join(", "));
+                        }
+                    }
+                }
+                Rule::reach => {
+                    let node = node.unwrap();
+                    for (dir, dir_str) in [(1, "<"), (0, ">")] {
+                        let reach = list(hive.reach(node, dir)?);
+                        println!("{} {dir_str} {reach}", quote(node));
+                    }
+                }
+                Rule::stage => {
+                    for (source, info) in hive.schedule(node.unwrap())? {
+                        println!(
+                            "{}: stage {}, {} out, {} more in",
+                            quote(source),
+                            info.stage(),
+                            info.count(0),
+                            info.count(1)
+                        );
+                    }
+                }
+                Rule::route => {
+                    let (src, dst) = (node.unwrap(), name(query.next().unwrap()));
+                    match hive.shortest_path(src, &dst)? {
+                        Some(path) => println!("{}", route(src, path)),
+                        None => println!("no path from {} to {}", quote(src), quote(&dst)),
+                    }
+                }
+                Rule::routes => {
+                    let (src, dst) = (node.unwrap(), name(query.next().unwrap()));
+                    // Simple paths can't be longer than the whole graph anyways
+                    let max_len = query
+                        .next()
+                        .map_or(usize::MAX, |n| number(n).unwrap_or(usize::MAX));
+                    for path in hive.simple_paths(src, &dst, max_len)? {
+                        println!("{}", route(src, path));
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(ControlFlow::Continue(()))
+}
+
+fn execute_action(hive: &mut Hive, action: Pair<Rule>) -> Result<(), HiveError> {
+    match action.as_rule() {
+        Rule::pipe => {
+            let mut pipe = action.into_inner();
+            let mut a = ends(pipe.next().unwrap());
+            while let Some(dir) = pipe.next() {
+                let b = ends(pipe.next().unwrap());
+                // Consecutive groups get wired completely
+                for ((a, a_port), (b, b_port)) in
+                    a.iter().flat_map(|a| b.iter().map(move |b| (a, b)))
+                {
+                    match dir.as_rule() {
+                        Rule::to => hive.pipe(a, b, [*a_port, *b_port])?,
+                        Rule::from => hive.pipe(b, a, [*b_port, *a_port])?,
+                        _ => unreachable!(),
+                    }
+                }
+                a = b;
+            }
+            Ok(())
+        }
+        Rule::delete => {
+            let delete = args(action).next().unwrap();
+            match delete.as_rule() {
+                Rule::unpipe => {
+                    let mut unpipe = delete.into_inner();
+                    let mut a = endpoint(unpipe.next().unwrap());
+                    while let Some(dir) = unpipe.next() {
+                        let b = endpoint(unpipe.next().unwrap());
+                        match dir.as_rule() {
+                            Rule::to => hive.unpipe(a.as_deref(), b.as_deref())?,
+                            Rule::from => hive.unpipe(b.as_deref(), a.as_deref())?,
+                            _ => unreachable!(),
+                        }
+                        a = b;
+                    }
+                    Ok(())
+                }
+                Rule::node => hive.remove_node(&name(delete)),
+                Rule::edge => hive.delete_edge(number(delete)?),
+                _ => unreachable!(),
+            }
+        }
+        rule @ (Rule::rename | Rule::merge) => {
+            let mut nodes = args(action);
+            let a = name(nodes.next().unwrap());
+            let b = name(nodes.next().unwrap());
+            match rule {
+                Rule::rename => hive.rename_node(&a, &b),
+                Rule::merge => hive.merge_nodes(&a, &b),
+                _ => unreachable!(),
+            }
+        }
+        Rule::operator => {
+            let mut operator = args(action);
+            let node = name(operator.next().unwrap());
+            let op = operator.next().unwrap().as_str();
+            let value = operator.next().map(real).transpose()?;
+            hive.set_operator(&node, op, value)
+        }
+        Rule::set => {
+            let mut set = args(action);
+            let (target, key) = attr(set.next().unwrap())?;
+            hive.set_attr(&target, &key, Some(value(set.last().unwrap())?))
+        }
+        Rule::unset => {
+            let (target, key) = attr(args(action).next().unwrap())?;
+            hive.set_attr(&target, &key, None)
+        }
+        Rule::declare => {
+            let mut declare = args(action);
+            let node = name(declare.next().unwrap());
+            while let Some(dir) = declare.next() {
+                let dir = match dir.as_rule() {
+                    Rule::to => 0,
+                    Rule::from => 1,
+                    _ => unreachable!(),
+                };
+                let ports = declare.next().unwrap();
+                let ports = match ports.as_rule() {
+                    Rule::port_list => Some(
+                        ports
+                            .into_inner()
+                            .filter(|port| port.as_rule() == Rule::port)
+                            .map(|port| port.as_str().to_string())
+                            .collect(),
+                    ),
+                    _ => None,
+                };
+                hive.set_ports(&node, dir, ports)?;
+            }
+            Ok(())
+        }
+        Rule::typedef => {
+            let mut typedef = args(action);
+            let name = typedef.next().unwrap().as_str();
+            let mut node_type = NodeType::default();
+            // A schema without direction declares outputs
+            let mut dir = 0;
+            for pair in typedef {
+                match pair.as_rule() {
+                    Rule::to => dir = 0,
+                    Rule::from => dir = 1,
+                    _ => {
+                        let ports = pair
+                            .into_inner()
+                            .filter(|port| port.as_rule() == Rule::port_schema)
+                            .map(port_schema)
+                            .collect::<Result<Vec<_>, _>>()?;
+                        node_type.ports[dir].extend(ports);
+                        dir = 0;
+                    }
+                }
+            }
+            hive.define_type(name, Some(node_type))
+        }
+        Rule::untype => hive.define_type(args(action).next().unwrap().as_str(), None),
+        Rule::typing => {
+            let mut typing = action.into_inner();
+            let node = name(typing.next().unwrap());
+            let node_type = typing.last().unwrap();
+            let node_type = (node_type.as_rule() == Rule::type_name).then(|| node_type.as_str());
+            hive.set_type(&node, node_type)
+        }
+        _ => unreachable!(),
+    }
+}
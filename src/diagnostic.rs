@@ -0,0 +1,180 @@
+use pest::error::{Error, ErrorVariant, InputLocation};
+
+use crate::{
+    command::{self, Rule},
+    eval,
+    hive::{Hive, HiveError},
+};
+
+/// Human wording of what a rule matches.
+fn describe(rule: Rule) -> String {
+    match rule {
+        Rule::node => "a node name",
+        Rule::edge => "an edge number",
+        Rule::number | Rule::greater_one => "a number",
+        Rule::end => "a node name",
+        Rule::port => "a port name",
+        Rule::group => "a group like `{a, b}`",
+        Rule::port_list => "ports like `{in1, in2}`",
+        Rule::type_name => "a type name",
+        Rule::kind => "a kind of values",
+        Rule::cardinality => "a limit like `[1]`",
+        Rule::port_schema => "a port like `in: signal`",
+        Rule::schema => "ports like `{in: signal}`",
+        Rule::assign => "`=`",
+        Rule::colon => "`:`",
+        Rule::comma => "`,`",
+        Rule::close_brace => "`}`",
+        Rule::close_bracket => "`]`",
+        Rule::from => "`<`",
+        Rule::to => "`>`",
+        Rule::any => "`*`",
+        Rule::unpipe => "a pattern like `a > *`",
+        Rule::key => "an attribute name",
+        Rule::function => "an operator",
+        Rule::attr => "an attribute like `a.gain`",
+        Rule::value | Rule::real | Rule::text => "a value",
+        Rule::path => "a file path",
+        Rule::staged => "`staged`",
+        Rule::kw_dot => "`dot`",
+        Rule::command | Rule::expr => "a command",
+        Rule::EOI => "the end of the line",
+        rule => return format!("{rule:?}"),
+    }
+    .to_string()
+}
+
+/// Enumerate like `a, b or c`.
+fn either(mut items: Vec<String>) -> String {
+    items.dedup();
+    match items.pop() {
+        Some(last) if !items.is_empty() => format!("{} or {last}", items.join(", ")),
+        Some(last) => last,
+        None => "something else".to_string(),
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Number of single character edits turning one word into the other.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<_> = b.chars().collect();
+    let mut row: Vec<_> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &b) in b.iter().enumerate() {
+            let next = (diagonal + usize::from(a != b))
+                .min(row[j] + 1)
+                .min(row[j + 1] + 1);
+            diagonal = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+/// Most similar candidate, as long as it is close enough to be a likely typo.
+pub fn closest<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    // Single letters are too short to tell a typo apart from another name
+    let limit = match word.chars().count() {
+        0 | 1 => 0,
+        2..=4 => 1,
+        _ => 2,
+    };
+    candidates
+        .into_iter()
+        .filter(|&candidate| candidate != word)
+        .map(|candidate| (distance(word, candidate), candidate))
+        .filter(|&(distance, _)| distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Explain why a command line failed to parse, underlining where it went wrong.
+pub fn parse_error(input: &str, error: Error<Rule>) -> HiveError {
+    // Lines read from stdin keep their line break, past which errors at the end would point
+    let input = input.trim_end_matches(['\r', '\n']);
+    let (start, end) = match error.location {
+        InputLocation::Pos(pos) => (pos, pos),
+        InputLocation::Span(span) => span,
+    };
+    let (start, end) = (start.min(input.len()), end.min(input.len()));
+    let line_start = input[..start].rfind('\n').map_or(0, |idx| idx + 1);
+    let line_end = input[start..]
+        .find('\n')
+        .map_or(input.len(), |idx| start + idx);
+    let line = &input[line_start..line_end];
+    let before = input[line_start..start].trim_end();
+    // Underline the whole word the error points into
+    let end = match start == end {
+        true => input[start..line_end]
+            .find(|c| !is_word(c))
+            .map_or(line_end, |idx| start + idx),
+        false => end,
+    };
+    let word_start = before.rfind(|c| !is_word(c)).map_or(0, |idx| idx + 1);
+    let previous = match before.chars().last() {
+        Some(c) if is_word(c) => &before[word_start..],
+        Some(c) => &before[before.len() - c.len_utf8()..],
+        None => "",
+    };
+
+    let command = before[before.rfind(';').map_or(0, |idx| idx + 1)..].trim_start();
+    let keywords = command::keywords();
+
+    let mut message = match error.variant {
+        ErrorVariant::ParsingError { mut positives, .. } => {
+            // A lone keyword also reads as a node name to pipe or type, which is hardly meant
+            let after_node = |rule: &Rule| matches!(rule, Rule::from | Rule::to | Rule::colon);
+            if keywords.contains(&command) && !positives.iter().all(after_node) {
+                positives.retain(|rule| !after_node(rule));
+            }
+            let expected = either(positives.into_iter().map(describe).collect());
+            match previous {
+                "" => format!("expected {expected}"),
+                previous => format!("expected {expected} after `{previous}`"),
+            }
+        }
+        ErrorVariant::CustomError { message } => message,
+    };
+    // A misspelled command reads as a node name lacking its pipe
+    let (mut start, mut end) = (start, end);
+    if command.chars().all(is_word) && !keywords.contains(&command) {
+        if let Some(keyword) = closest(command, keywords) {
+            message = format!("unknown command `{command}`, did you mean `{keyword}`?");
+            end = line_start + before.len();
+            start = end - command.len();
+        }
+    }
+
+    let column = input[line_start..start].chars().count();
+    let width = input[start..end].chars().count().max(1);
+    HiveError::Parse(format!(
+        "{message}\n  | {line}\n  | {}{}",
+        " ".repeat(column),
+        "^".repeat(width)
+    ))
+}
+
+/// Add a suggestion to errors about misspelled names.
+pub fn suggest(hive: &Hive, error: HiveError) -> HiveError {
+    let suggestion = match &error {
+        HiveError::UnknownNode(node) => closest(node, hive.node_names()),
+        HiveError::UnknownOperator(op) => closest(op, eval::OPERATORS),
+        HiveError::UnknownType(name) => closest(name, hive.type_names()),
+        HiveError::UnknownPort(node, port) => {
+            let ports = (0..2)
+                .filter_map(|dir| hive.ports(node, dir).ok())
+                .flatten();
+            closest(port, ports.filter_map(|(port, _)| port))
+        }
+        _ => None,
+    };
+    match suggestion {
+        Some(suggestion) => HiveError::Suggestion(Box::new(error), suggestion.to_string()),
+        None => error,
+    }
+}
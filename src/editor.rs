@@ -0,0 +1,89 @@
+use rustyline::{
+    completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator, Context,
+    Helper,
+};
+
+use crate::command;
+
+/// Completes command keywords and node names in the line editor
+pub struct HiveHelper {
+    keywords: Vec<&'static str>,
+    /// Names of the nodes in the hive, refreshed before every line
+    pub nodes: Vec<String>,
+}
+
+impl HiveHelper {
+    pub fn new(keywords: Vec<&'static str>) -> Self {
+        HiveHelper {
+            keywords,
+            nodes: Vec::new(),
+        }
+    }
+}
+
+impl Completer for HiveHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        // Within an open quote the name continues past spaces and the like
+        let quoted = open_quote(&line[..pos]);
+        let start = quoted.unwrap_or_else(|| {
+            line[..pos]
+                .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .map_or(0, |idx| {
+                    idx + line[idx..].chars().next().unwrap().len_utf8()
+                })
+        });
+        let word = &line[start..pos];
+        let keywords = self
+            .keywords
+            .iter()
+            .filter(|keyword| quoted.is_none() && keyword.starts_with(word))
+            .map(|keyword| keyword.to_string());
+        // Nodes complete to their quoted form, so names like `rev 1` read back as a single node
+        let nodes = self.nodes.iter().filter_map(|node| {
+            let candidate = command::quote(node);
+            let typed = match quoted {
+                Some(_) => &candidate,
+                None => node.as_str(),
+            };
+            typed.starts_with(word).then(|| candidate.into_owned())
+        });
+        let mut candidates: Vec<_> = keywords.chain(nodes).collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+/// Position of the quote a text leaves open, if any.
+fn open_quote(text: &str) -> Option<usize> {
+    let mut open = None;
+    let mut chars = text.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '"' => open = if open.is_some() { None } else { Some(idx) },
+            // Escaped characters can't close the quote
+            '\\' if open.is_some() => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    open
+}
+
+impl Hinter for HiveHelper {
+    type Hint = String;
+}
+
+impl Highlighter for HiveHelper {}
+
+impl Validator for HiveHelper {}
+
+impl Helper for HiveHelper {}
@@ -0,0 +1,38 @@
+use crate::hive::{Attrs, Value};
+
+/// Value computed by a node or the reason why it couldn't be computed
+pub type Output = Result<f64, String>;
+
+/// Names of all operators which can be assigned to a node
+pub const OPERATORS: [&str; 8] = ["const", "add", "sub", "mul", "div", "min", "max", "print"];
+
+/// Apply the operator in the `op` attribute of a node to its inputs.
+/// `const` yields the `value` attribute and `print` passes on the sum of its inputs to get reported by `eval`.
+/// Failures of upstream nodes are passed on as they are.
+pub fn operate(node: &str, attrs: &Attrs, inputs: &[Output]) -> Output {
+    let op = match attrs.get("op") {
+        Some(Value::Text(op)) => op.as_str(),
+        Some(op) => return Err(format!("node `{node}` has the invalid operator {op}")),
+        None => return Err(format!("node `{node}` has no operator")),
+    };
+    let inputs = inputs.iter().cloned().collect::<Result<Vec<_>, _>>()?;
+    let first = |op: &str| {
+        inputs
+            .first()
+            .copied()
+            .ok_or_else(|| format!("operator {op} of node `{node}` lacks inputs"))
+    };
+    match op {
+        "const" => match attrs.get("value") {
+            Some(Value::Number(value)) => Ok(*value),
+            _ => Err(format!("constant `{node}` has no numeric value")),
+        },
+        "add" | "print" => Ok(inputs.iter().sum()),
+        "sub" => Ok(first(op)? - inputs[1..].iter().sum::<f64>()),
+        "mul" => Ok(inputs.iter().product()),
+        "div" => Ok(first(op)? / inputs[1..].iter().product::<f64>()),
+        "min" => Ok(inputs.iter().copied().fold(first(op)?, f64::min)),
+        "max" => Ok(inputs.iter().copied().fold(first(op)?, f64::max)),
+        _ => Err(format!("node `{node}` has the unknown operator {op}")),
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Sender},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::graph::{EdgeIndex, Graph, NodeIndex};
+
+/// Work done by a node of a pipeline
+pub trait NodeProcessor {
+    type Output: Clone + Send;
+
+    /// Compute the output from the outputs of the upstream nodes, ordered by the numbers of the incoming edges.
+    fn process(&self, inputs: &[Self::Output]) -> Self::Output;
+}
+
+/// Runs the nodes of a graph on a pool of worker threads.
+/// A node starts as soon as all of its upstream nodes are done, so the nodes of a stage run concurrently.
+/// Since the inputs of a node are ordered by edge, the outputs don't depend on the number of threads.
+pub struct Executor {
+    threads: usize,
+}
+
+impl Executor {
+    pub fn new(threads: usize) -> Self {
+        Executor {
+            threads: threads.max(1),
+        }
+    }
+
+    /// Process every node without an output yet and store its output, or yield the edges of a cycle preventing it.
+    /// Existing outputs are taken as they are, so invalidating the downstream of a change is up to the caller.
+    /// A panicking node stops the pool and the panic resumes on the calling thread.
+    pub fn run<N, E>(
+        &self,
+        graph: &Graph<N, E>,
+        outputs: &mut HashMap<NodeIndex, N::Output>,
+    ) -> Result<(), Vec<EdgeIndex>>
+    where
+        N: NodeProcessor + Sync,
+    {
+        self.run_nodes(graph, graph.node_indices(), outputs)
+    }
+
+    /// Like `run`, but limited to the given nodes, which need to include the upstream of any of them without an output.
+    pub fn run_nodes<N, E>(
+        &self,
+        graph: &Graph<N, E>,
+        nodes: impl IntoIterator<Item = NodeIndex>,
+        outputs: &mut HashMap<NodeIndex, N::Output>,
+    ) -> Result<(), Vec<EdgeIndex>>
+    where
+        N: NodeProcessor + Sync,
+    {
+        graph.toposort()?;
+        // Number of upstream nodes each node is still waiting for
+        let mut pending: HashMap<_, _> = nodes
+            .into_iter()
+            .filter(|idx| !outputs.contains_key(idx))
+            .map(|idx| {
+                let count = graph
+                    .neighbors(idx, 1)
+                    .filter(|(prior, _)| !outputs.contains_key(prior))
+                    .count();
+                (idx, count)
+            })
+            .collect();
+        let mut ready: Vec<_> = pending
+            .iter()
+            .filter(|(_, &count)| count == 0)
+            .map(|(&idx, _)| idx)
+            .collect();
+        ready.sort();
+
+        let (job_tx, job_rx) = mpsc::channel::<(NodeIndex, &N, Vec<N::Output>)>();
+        let (done_tx, done_rx) = mpsc::channel();
+        let job_rx = Mutex::new(job_rx);
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(pending.len()) {
+                let job_rx = &job_rx;
+                let done_tx = done_tx.clone();
+                scope.spawn(move || loop {
+                    let job = job_rx.lock().unwrap().recv();
+                    let Ok((idx, node, inputs)) = job else {
+                        // The channel closes once all nodes are done
+                        break;
+                    };
+                    let output = panic::catch_unwind(AssertUnwindSafe(|| node.process(&inputs)));
+                    if done_tx.send((idx, output)).is_err() {
+                        break;
+                    }
+                });
+            }
+            // Only the workers keep the channel open, so it closes if all of them die
+            drop(done_tx);
+
+            let dispatch =
+                |job_tx: &Sender<_>, idx: NodeIndex, outputs: &HashMap<NodeIndex, N::Output>| {
+                    let mut edges: Vec<_> = graph.edges(idx, 1).collect();
+                    edges.sort();
+                    let inputs = edges
+                        .into_iter()
+                        .map(|edge| outputs[&graph.src_dst(edge).unwrap()[0]].clone())
+                        .collect();
+                    job_tx.send((idx, graph[idx].data(), inputs)).unwrap();
+                };
+            let mut running = ready.len();
+            for idx in ready {
+                dispatch(&job_tx, idx, outputs);
+            }
+            while running > 0 {
+                let Ok((idx, output)) = done_rx.recv() else {
+                    panic!("the worker pool died");
+                };
+                let output = match output {
+                    Ok(output) => output,
+                    Err(payload) => {
+                        // Idle workers quit on the closed channel, so the scope is able to join them
+                        drop(job_tx);
+                        panic::resume_unwind(payload);
+                    }
+                };
+                running -= 1;
+                outputs.insert(idx, output);
+                for (next, _) in graph.neighbors(idx, 0) {
+                    let Some(count) = pending.get_mut(&next) else {
+                        continue;
+                    };
+                    *count -= 1;
+                    if *count == 0 {
+                        dispatch(&job_tx, next, outputs);
+                        running += 1;
+                    }
+                }
+            }
+            drop(job_tx);
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Folds its inputs in order, so any mixup of inputs changes the output.
+    struct Fold {
+        seed: u64,
+        panics: bool,
+    }
+
+    impl NodeProcessor for Fold {
+        type Output = u64;
+
+        fn process(&self, inputs: &[u64]) -> u64 {
+            assert!(!self.panics, "node {} failed", self.seed);
+            inputs.iter().fold(self.seed, |acc, input| {
+                acc.wrapping_mul(31).wrapping_add(*input)
+            })
+        }
+    }
+
+    fn graph(nodes: u64, panicking: Option<u64>) -> Graph<Fold, ()> {
+        let mut graph = Graph::default();
+        let nodes: Vec<_> = (0..nodes)
+            .map(|seed| {
+                let panics = panicking == Some(seed);
+                graph.add_node(Fold { seed, panics })
+            })
+            .collect();
+        // Edges only point forward, from several predecessors each
+        for (dst, &idx) in nodes.iter().enumerate().skip(1) {
+            for src in [dst / 2, dst - 1, dst * 7 % dst] {
+                graph.add_edge(nodes[src], idx, ()).unwrap();
+            }
+        }
+        graph
+    }
+
+    fn run(graph: &Graph<Fold, ()>, threads: usize) -> HashMap<NodeIndex, u64> {
+        let mut outputs = HashMap::new();
+        Executor::new(threads).run(graph, &mut outputs).unwrap();
+        outputs
+    }
+
+    #[test]
+    fn outputs_are_independent_of_threads() {
+        let graph = graph(200, None);
+        let outputs = run(&graph, 1);
+        assert_eq!(outputs.len(), 200);
+        for threads in [2, 4, 16] {
+            assert_eq!(run(&graph, threads), outputs);
+        }
+    }
+
+    #[test]
+    fn cached_outputs_are_kept() {
+        let graph = graph(50, None);
+        let outputs = run(&graph, 1);
+        let mut partial = outputs.clone();
+        let nodes: Vec<_> = graph.node_indices().collect();
+        for idx in &nodes[20..] {
+            partial.remove(idx);
+        }
+        Executor::new(4).run(&graph, &mut partial).unwrap();
+        assert_eq!(partial, outputs);
+    }
+
+    #[test]
+    fn panics_propagate() {
+        for threads in [1, 4] {
+            let graph = graph(20, Some(3));
+            let result = panic::catch_unwind(AssertUnwindSafe(|| run(&graph, threads)));
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let mut graph = graph(5, None);
+        let nodes: Vec<_> = graph.node_indices().collect();
+        let edge = graph.add_edge(nodes[4], nodes[1], ()).unwrap();
+        let cycle = Executor::new(2)
+            .run(&graph, &mut HashMap::new())
+            .unwrap_err();
+        assert!(cycle.contains(&edge));
+    }
+}
@@ -1,38 +1,67 @@
 use core::{
-    mem,
+    fmt, mem,
     ops::{Index, IndexMut},
 };
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphError {
+    /// The index points beyond all slots
+    UnknownNode(NodeIndex),
+    UnknownEdge(EdgeIndex),
+    /// The slot has been vacated or recycled since the index was handed out
+    StaleNode(NodeIndex),
+    StaleEdge(EdgeIndex),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::UnknownNode(idx) => write!(f, "unknown node index {}", idx.0),
+            GraphError::UnknownEdge(idx) => write!(f, "unknown edge index {}", idx.0),
+            GraphError::StaleNode(idx) => write!(f, "obsolete node index {}:{}", idx.0, idx.1),
+            GraphError::StaleEdge(idx) => write!(f, "obsolete edge index {}:{}", idx.0, idx.1),
+        }
+    }
+}
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub struct NodeIndex(pub(crate) usize);
+impl std::error::Error for GraphError {}
 
-impl<N> Index<NodeIndex> for Vec<Result<Node<N>, ()>> {
-    type Output = Result<Node<N>, ()>;
+/// Tags a slot with the lifetime of its occupant to deny false usage of obsolete handles
+type Generation = u32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeIndex(pub(crate) usize, Generation);
+
+impl<N> Index<NodeIndex> for Vec<Slot<Node<N>>> {
+    type Output = Slot<Node<N>>;
 
     fn index(&self, index: NodeIndex) -> &Self::Output {
         &self[index.0]
     }
 }
 
-impl<N> IndexMut<NodeIndex> for Vec<Result<Node<N>, ()>> {
+impl<N> IndexMut<NodeIndex> for Vec<Slot<Node<N>>> {
     fn index_mut(&mut self, index: NodeIndex) -> &mut Self::Output {
         &mut self[index.0]
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct EdgeIndex(pub(crate) usize);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EdgeIndex(pub(crate) usize, Generation);
 
-impl<E> Index<EdgeIndex> for Vec<Result<Edge<E>, ()>> {
-    type Output = Result<Edge<E>, ()>;
+impl<E> Index<EdgeIndex> for Vec<Slot<Edge<E>>> {
+    type Output = Slot<Edge<E>>;
 
     fn index(&self, index: EdgeIndex) -> &Self::Output {
         &self[index.0]
     }
 }
 
-impl<E> IndexMut<EdgeIndex> for Vec<Result<Edge<E>, ()>> {
+impl<E> IndexMut<EdgeIndex> for Vec<Slot<Edge<E>>> {
     fn index_mut(&mut self, index: EdgeIndex) -> &mut Self::Output {
         &mut self[index.0]
     }
@@ -48,6 +77,16 @@ pub struct Node<N> {
     data: N,
 }
 
+impl<N> Node<N> {
+    pub fn data(&self) -> &N {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut N {
+        &mut self.data
+    }
+}
+
 #[derive(Debug)]
 pub struct Edge<E> {
     /// [src, dst]
@@ -55,27 +94,149 @@ pub struct Edge<E> {
     data: E,
 }
 
-#[derive(Default)]
+impl<E> Edge<E> {
+    pub fn data(&self) -> &E {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut E {
+        &mut self.data
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Slot<T> {
+    generation: Generation,
+    /// Vacant slots link to the next vacant slot of the free list
+    entry: Result<T, Option<usize>>,
+}
+
+impl<T> Slot<T> {
+    fn get(&self, generation: Generation) -> Option<&T> {
+        self.entry
+            .as_ref()
+            .ok()
+            .filter(|_| self.generation == generation)
+    }
+
+    fn get_mut(&mut self, generation: Generation) -> Option<&mut T> {
+        match self.entry.as_mut() {
+            Ok(value) if self.generation == generation => Some(value),
+            _ => None,
+        }
+    }
+
+    fn unwrap(&self) -> &T {
+        self.entry.as_ref().ok().unwrap()
+    }
+
+    /// Position and generation of the slot which is occupied next.
+    fn vacant(slots: &[Self], free: Option<usize>) -> (usize, Generation) {
+        free.map_or((slots.len(), 0), |idx| (idx, slots[idx].generation))
+    }
+
+    fn occupy(slots: &mut Vec<Self>, free: &mut Option<usize>, value: T) -> (usize, Generation) {
+        let (idx, generation) = Self::vacant(slots, *free);
+        if idx == slots.len() {
+            slots.push(Slot {
+                generation,
+                entry: Ok(value),
+            });
+        } else {
+            *free = mem::replace(&mut slots[idx].entry, Ok(value))
+                .err()
+                .unwrap();
+        }
+        (idx, generation)
+    }
+
+    fn vacate(slots: &mut [Self], free: &mut Option<usize>, idx: usize) -> T {
+        let slot = &mut slots[idx];
+        slot.generation = slot.generation.wrapping_add(1);
+        mem::replace(&mut slot.entry, Err(free.replace(idx)))
+            .ok()
+            .unwrap()
+    }
+}
+
 pub struct Graph<N, E> {
-    // TODO: Cache and recycle freed indices via Error type.
-    // Turn Error type into Option(Index) and add additional
-    // Option(Index) cache members. Introduce generation
-    // concept to deny false usage of obsolete Index handles.
-    pub(crate) nodes: Vec<Result<Node<N>, ()>>,
-    pub(crate) edges: Vec<Result<Edge<E>, ()>>,
+    pub(crate) nodes: Vec<Slot<Node<N>>>,
+    pub(crate) edges: Vec<Slot<Edge<E>>>,
+    /// Heads of the free lists of vacant slots
+    free_node: Option<usize>,
+    free_edge: Option<usize>,
+    /// Nodes whose derived information is outdated. Along with a node its whole downstream is dirty.
+    dirty: HashSet<NodeIndex>,
+    /// Topological order maintained on every change while the graph is acyclic, or else a cycle preventing it
+    sorting: Sorting,
+}
+
+impl<N, E> Default for Graph<N, E> {
+    fn default() -> Self {
+        Graph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            free_node: None,
+            free_edge: None,
+            dirty: HashSet::new(),
+            sorting: Sorting::Sorted(Order::default()),
+        }
+    }
+}
+
+enum Sorting {
+    Sorted(Order),
+    /// The edges of a cycle, which stays valid as long as none of them is removed
+    Cyclic(Vec<EdgeIndex>),
+    /// Unknown until `toposort_mut` sorts from scratch
+    Stale,
+}
+
+#[derive(Default)]
+struct Order {
+    /// Nodes in topological order, where removed nodes leave `None` until the next compaction
+    nodes: Vec<Option<NodeIndex>>,
+    position: HashMap<NodeIndex, usize>,
+}
+
+impl Order {
+    fn new(nodes: Vec<NodeIndex>) -> Self {
+        let position = nodes
+            .iter()
+            .enumerate()
+            .map(|(pos, &idx)| (idx, pos))
+            .collect();
+        Order {
+            nodes: nodes.into_iter().map(Some).collect(),
+            position,
+        }
+    }
+
+    fn remove(&mut self, idx: NodeIndex) {
+        let pos = self.position.remove(&idx).unwrap();
+        self.nodes[pos] = None;
+        // Compacting only once the gaps make up half of the order keeps removals cheap on average
+        if self.position.len() * 2 < self.nodes.len() {
+            *self = Order::new(self.nodes().collect());
+        }
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.nodes.iter().flatten().copied()
+    }
 }
 
 impl<N, E> Index<NodeIndex> for Graph<N, E> {
     type Output = Node<N>;
 
     fn index(&self, index: NodeIndex) -> &Self::Output {
-        self.nodes[index].as_ref().unwrap()
+        self.node(index).unwrap()
     }
 }
 
 impl<N, E> IndexMut<NodeIndex> for Graph<N, E> {
     fn index_mut(&mut self, index: NodeIndex) -> &mut Self::Output {
-        self.nodes[index].as_mut().unwrap()
+        self.node_mut(index).unwrap()
     }
 }
 
@@ -83,55 +244,126 @@ impl<N, E> Index<EdgeIndex> for Graph<N, E> {
     type Output = Edge<E>;
 
     fn index(&self, index: EdgeIndex) -> &Self::Output {
-        self.edges[index].as_ref().unwrap()
+        self.edge(index).unwrap()
     }
 }
 
 impl<N, E> IndexMut<EdgeIndex> for Graph<N, E> {
     fn index_mut(&mut self, index: EdgeIndex) -> &mut Self::Output {
-        self.edges[index].as_mut().unwrap()
+        self.edge_mut(index).unwrap()
     }
 }
 
 impl<N, E> Graph<N, E> {
     pub fn add_node(&mut self, node: N) -> NodeIndex {
-        let node_idx = NodeIndex(
-            self.nodes
-                .iter()
-                .position(Result::is_err)
-                .unwrap_or(self.nodes.len()),
-        );
-        let node = Ok(Node {
+        let node = Node {
             data: node,
             next: [None; 2],
-        });
-        if node_idx.0 == self.nodes.len() {
-            self.nodes.push(node)
-        } else {
-            self.nodes[node_idx.0] = node
+        };
+        let (idx, generation) = Slot::occupy(&mut self.nodes, &mut self.free_node, node);
+        let idx = NodeIndex(idx, generation);
+        self.dirty.insert(idx);
+        if let Sorting::Sorted(order) = &mut self.sorting {
+            // Without any edges the node fits anywhere
+            order.position.insert(idx, order.nodes.len());
+            order.nodes.push(Some(idx));
         }
-        node_idx
+        idx
     }
 
-    pub fn add_edge(&mut self, src_idx: NodeIndex, dst_idx: NodeIndex, edge: E) -> EdgeIndex {
-        let edge_idx = EdgeIndex(
-            self.edges
-                .iter()
-                .position(Result::is_err)
-                .unwrap_or(self.edges.len()),
-        );
+    pub fn add_edge(
+        &mut self,
+        src_idx: NodeIndex,
+        dst_idx: NodeIndex,
+        edge: E,
+    ) -> Result<EdgeIndex, GraphError> {
+        self.node(src_idx)?;
+        self.node(dst_idx)?;
+        let (idx, generation) = Slot::vacant(&self.edges, self.free_edge);
+        let edge_idx = EdgeIndex(idx, generation);
         let src = self[src_idx].next[0].replace(edge_idx).ok_or(src_idx);
         let dst = self[dst_idx].next[1].replace(edge_idx).ok_or(dst_idx);
-        let edge = Ok(Edge {
+        let edge = Edge {
             data: edge,
             next: [src, dst],
-        });
-        if edge_idx.0 == self.edges.len() {
-            self.edges.push(edge)
-        } else {
-            self.edges[edge_idx.0] = edge
-        }
-        edge_idx
+        };
+        Slot::occupy(&mut self.edges, &mut self.free_edge, edge);
+        self.touch(dst_idx);
+        self.reorder(src_idx, dst_idx);
+        Ok(edge_idx)
+    }
+
+    pub fn node(&self, idx: NodeIndex) -> Result<&Node<N>, GraphError> {
+        let slot = self.nodes.get(idx.0).ok_or(GraphError::UnknownNode(idx))?;
+        slot.get(idx.1).ok_or(GraphError::StaleNode(idx))
+    }
+
+    fn node_mut(&mut self, idx: NodeIndex) -> Result<&mut Node<N>, GraphError> {
+        let slot = self
+            .nodes
+            .get_mut(idx.0)
+            .ok_or(GraphError::UnknownNode(idx))?;
+        slot.get_mut(idx.1).ok_or(GraphError::StaleNode(idx))
+    }
+
+    pub fn edge(&self, idx: EdgeIndex) -> Result<&Edge<E>, GraphError> {
+        let slot = self.edges.get(idx.0).ok_or(GraphError::UnknownEdge(idx))?;
+        slot.get(idx.1).ok_or(GraphError::StaleEdge(idx))
+    }
+
+    fn edge_mut(&mut self, idx: EdgeIndex) -> Result<&mut Edge<E>, GraphError> {
+        let slot = self
+            .edges
+            .get_mut(idx.0)
+            .ok_or(GraphError::UnknownEdge(idx))?;
+        slot.get_mut(idx.1).ok_or(GraphError::StaleEdge(idx))
+    }
+
+    /// Resolve the node currently living in the given slot.
+    pub fn node_index(&self, idx: usize) -> Option<NodeIndex> {
+        let slot = self.nodes.get(idx)?;
+        slot.entry
+            .is_ok()
+            .then_some(NodeIndex(idx, slot.generation))
+    }
+
+    /// Resolve the edge currently living in the given slot.
+    pub fn edge_index(&self, idx: usize) -> Option<EdgeIndex> {
+        let slot = self.edges.get(idx)?;
+        slot.entry
+            .is_ok()
+            .then_some(EdgeIndex(idx, slot.generation))
+    }
+
+    pub fn node_indices(&self) -> impl Iterator<Item = NodeIndex> + '_ {
+        (0..self.nodes.len()).filter_map(|idx| self.node_index(idx))
+    }
+
+    pub fn edge_indices(&self) -> impl Iterator<Item = EdgeIndex> + '_ {
+        (0..self.edges.len()).filter_map(|idx| self.edge_index(idx))
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.node_indices().count()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edge_indices().count()
+    }
+
+    /// Upper bound of all node slots, vacant or not.
+    pub fn node_bound(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Upper bound of all edge slots, vacant or not.
+    pub fn edge_bound(&self) -> usize {
+        self.edges.len()
+    }
+
+    /// Drop all nodes and edges and forget about their slots.
+    pub fn clear(&mut self) {
+        *self = Self::default();
     }
 
     fn next(&self, idx: NogeIndex, dir: usize) -> NogeIndex {
@@ -158,50 +390,56 @@ impl<N, E> Graph<N, E> {
         }
     }
 
-    pub(crate) fn src_dst(&mut self, idx: EdgeIndex) -> Option<[NodeIndex; 2]> {
-        self.edges.get(idx.0).and_then(|r| {
-            r.as_ref().ok().map(|edge| {
-                std::array::from_fn(|dir| {
-                    let mut noge = edge.next[dir];
-                    while let Ok(edge_idx) = noge {
-                        noge = self[edge_idx].next[dir];
-                    }
-                    noge.unwrap_err()
-                })
+    pub(crate) fn src_dst(&self, idx: EdgeIndex) -> Result<[NodeIndex; 2], GraphError> {
+        self.edge(idx).map(|edge| {
+            std::array::from_fn(|dir| {
+                let mut noge = edge.next[dir];
+                while let Ok(edge_idx) = noge {
+                    noge = self[edge_idx].next[dir];
+                }
+                noge.unwrap_err()
             })
         })
     }
 
     pub(crate) fn remove_edge_unchecked(&mut self, idx: EdgeIndex) -> E {
+        let [_, dst] = self.src_dst(idx).unwrap();
+        self.touch(dst);
         for dir in 0..2 {
             self.unchain(idx, dir);
         }
-        mem::replace(&mut self.edges[idx], Err(())).unwrap().data
+        self.break_cycle(idx);
+        Slot::vacate(&mut self.edges, &mut self.free_edge, idx.0).data
     }
 
-    pub fn remove_edge(&mut self, idx: EdgeIndex) -> Option<E> {
-        if idx.0 < self.edges.len() && self.nodes[idx.0].is_ok() {
-            return Some(self.remove_edge_unchecked(idx));
-        }
-        None
+    pub fn remove_edge(&mut self, idx: EdgeIndex) -> Result<E, GraphError> {
+        self.edge(idx)?;
+        Ok(self.remove_edge_unchecked(idx))
     }
 
     pub(crate) fn remove_node_unchecked(&mut self, idx: NodeIndex) -> N {
+        let next: Vec<_> = self.neighbors(idx, 0).map(|(next, _)| next).collect();
+        for next in next {
+            self.touch(next);
+        }
+        self.dirty.remove(&idx);
+        if let Sorting::Sorted(order) = &mut self.sorting {
+            order.remove(idx);
+        }
         for dir in 0..2 {
             while let Some(edge_idx) = self[idx].next[dir] {
                 self[idx].next[dir] = self[edge_idx].next[dir].ok();
                 self.unchain(edge_idx, dir ^ 1);
-                self.edges[edge_idx] = Err(());
+                self.break_cycle(edge_idx);
+                Slot::vacate(&mut self.edges, &mut self.free_edge, edge_idx.0);
             }
         }
-        mem::replace(&mut self.nodes[idx], Err(())).unwrap().data
+        Slot::vacate(&mut self.nodes, &mut self.free_node, idx.0).data
     }
 
-    pub fn remove_node(&mut self, idx: NodeIndex) -> Option<N> {
-        if idx.0 < self.nodes.len() && self.edges[idx.0].is_ok() {
-            return Some(self.remove_node_unchecked(idx));
-        }
-        None
+    pub fn remove_node(&mut self, idx: NodeIndex) -> Result<N, GraphError> {
+        self.node(idx)?;
+        Ok(self.remove_node_unchecked(idx))
     }
 
     /// Gather traversal information with respect to the given node and direction.
@@ -225,7 +463,346 @@ impl<N, E> Graph<N, E> {
                 }
             }
         }
-        return schedule;
+        schedule
+    }
+
+    /// Mark the node and its whole downstream as dirty, e.g. after changing its data.
+    pub fn touch(&mut self, idx: NodeIndex) {
+        if !self.dirty.insert(idx) {
+            // The downstream is dirty already
+            return;
+        }
+        let mut stack = vec![idx];
+        while let Some(idx) = stack.pop() {
+            let next: Vec<_> = self.neighbors(idx, 0).map(|(next, _)| next).collect();
+            for next in next {
+                if self.dirty.insert(next) {
+                    stack.push(next);
+                }
+            }
+        }
+    }
+
+    /// Nodes changed since the last `clean`, including new nodes and the downstream of changed edges.
+    pub fn dirty_nodes(&self) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.dirty.iter().copied()
+    }
+
+    pub fn clean(&mut self) {
+        self.dirty.clear();
+    }
+
+    /// Restore the topological order after the new edge from `src` to `dst` by the algorithm of Pearce and Kelly.
+    /// Only nodes placed between both ends get shuffled.
+    fn reorder(&mut self, src: NodeIndex, dst: NodeIndex) {
+        let Sorting::Sorted(order) = &self.sorting else {
+            // A cycle stays a cycle with one more edge
+            return;
+        };
+        let (lower, upper) = (order.position[&dst], order.position[&src]);
+        if lower > upper {
+            return;
+        }
+        let reach = |start: NodeIndex, dir: usize| {
+            let mut reached = vec![start];
+            let mut visited = HashSet::from([start]);
+            let mut stack = vec![start];
+            while let Some(idx) = stack.pop() {
+                for (next, _) in self.neighbors(idx, dir) {
+                    let pos = order.position[&next];
+                    if (lower..=upper).contains(&pos) && visited.insert(next) {
+                        reached.push(next);
+                        stack.push(next);
+                    }
+                }
+            }
+            reached
+        };
+        // Whatever is reachable from `dst` moves behind whatever reaches `src`
+        let forward = reach(dst, 0);
+        let cyclic = forward.contains(&src);
+        let nodes: Vec<_> = [reach(src, 1), forward]
+            .into_iter()
+            .flat_map(|mut nodes| {
+                nodes.sort_by_key(|idx| order.position[idx]);
+                nodes
+            })
+            .collect();
+        let mut positions: Vec<_> = nodes.iter().map(|idx| order.position[idx]).collect();
+        positions.sort();
+        if cyclic {
+            // The cycle gets extracted by the next `toposort_mut`
+            self.sorting = Sorting::Stale;
+            return;
+        }
+        let Sorting::Sorted(order) = &mut self.sorting else {
+            unreachable!();
+        };
+        for (idx, pos) in nodes.into_iter().zip(positions) {
+            order.nodes[pos] = Some(idx);
+            order.position.insert(idx, pos);
+        }
+    }
+
+    /// Forget the known cycle if the edge about to be removed is part of it.
+    /// Other removals leave the cycle in place, so they don't cost a sort from scratch.
+    fn break_cycle(&mut self, idx: EdgeIndex) {
+        if matches!(&self.sorting, Sorting::Cyclic(cycle) if cycle.contains(&idx)) {
+            self.sorting = Sorting::Stale;
+        }
+    }
+
+    /// Order all nodes such that every edge points forward or yield the edges of a cycle preventing it.
+    /// Only after a cycle got broken or closed this sorts from scratch, which `toposort_mut` keeps for later calls.
+    pub fn toposort(&self) -> Result<Vec<NodeIndex>, Vec<EdgeIndex>> {
+        match &self.sorting {
+            Sorting::Sorted(order) => Ok(order.nodes().collect()),
+            Sorting::Cyclic(cycle) => Err(cycle.clone()),
+            Sorting::Stale => self.sort(),
+        }
+    }
+
+    /// Like `toposort`, but caching the outcome of a sort from scratch.
+    pub fn toposort_mut(&mut self) -> Result<Vec<NodeIndex>, Vec<EdgeIndex>> {
+        if let Sorting::Stale = self.sorting {
+            self.sorting = match self.sort() {
+                Ok(nodes) => Sorting::Sorted(Order::new(nodes)),
+                Err(cycle) => Sorting::Cyclic(cycle),
+            };
+        }
+        self.toposort()
+    }
+
+    /// Sort from scratch by Kahn's algorithm.
+    fn sort(&self) -> Result<Vec<NodeIndex>, Vec<EdgeIndex>> {
+        let mut incoming: HashMap<_, _> = self
+            .node_indices()
+            .map(|idx| (idx, self.edges(idx, 1).count()))
+            .collect();
+        let mut queue: VecDeque<_> = self
+            .node_indices()
+            .filter(|idx| incoming[idx] == 0)
+            .collect();
+        let mut order = Vec::with_capacity(incoming.len());
+        while let Some(idx) = queue.pop_front() {
+            order.push(idx);
+            for (neighbor, _) in self.neighbors(idx, 0) {
+                let count = incoming.get_mut(&neighbor).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        if order.len() == incoming.len() {
+            return Ok(order);
+        }
+
+        // Every node left over has a predecessor left over as well, so walking upstream runs in circles
+        let mut idx = self.node_indices().find(|idx| incoming[idx] > 0).unwrap();
+        let mut visited = HashMap::new();
+        let mut path = Vec::new();
+        while !visited.contains_key(&idx) {
+            visited.insert(idx, path.len());
+            let (prior, edge) = self
+                .neighbors(idx, 1)
+                .find(|(prior, _)| incoming[prior] > 0)
+                .unwrap();
+            path.push(edge);
+            idx = prior;
+        }
+        let mut cycle = path.split_off(visited[&idx]);
+        cycle.reverse();
+        Err(cycle)
+    }
+
+    /// Strongly connected components in topological order of the condensation, found by Kosaraju's algorithm.
+    pub fn scc(&self) -> Vec<Vec<NodeIndex>> {
+        // Order the nodes by the time their downstream search finishes
+        let mut visited = HashSet::new();
+        let mut finished = Vec::with_capacity(self.node_count());
+        for root in self.node_indices() {
+            if !visited.insert(root) {
+                continue;
+            }
+            let mut stack = vec![(root, self.neighbors(root, 0))];
+            while let Some((idx, neighbors)) = stack.last_mut() {
+                let idx = *idx;
+                match neighbors.find(|&(neighbor, _)| visited.insert(neighbor)) {
+                    Some((neighbor, _)) => stack.push((neighbor, self.neighbors(neighbor, 0))),
+                    None => {
+                        finished.push(idx);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+
+        // Searching upstream in reverse finishing order confines each search to a single component
+        let mut assigned = HashSet::new();
+        let mut components = Vec::new();
+        for root in finished.into_iter().rev() {
+            if !assigned.insert(root) {
+                continue;
+            }
+            let mut component = vec![root];
+            let mut next = 0;
+            while let Some(&idx) = component.get(next) {
+                for (neighbor, _) in self.neighbors(idx, 1) {
+                    if assigned.insert(neighbor) {
+                        component.push(neighbor);
+                    }
+                }
+                next += 1;
+            }
+            component.sort();
+            components.push(component);
+        }
+        components
+    }
+
+    /// Acyclic graph with a node per strongly connected component and an edge per connected pair of components.
+    /// The node at slot `i` holds the `i`-th component of `scc()` and edges hold all edges they stand for.
+    pub fn condense(&self) -> Graph<Vec<NodeIndex>, Vec<EdgeIndex>> {
+        let mut condensed = Graph::default();
+        let mut component = HashMap::new();
+        for nodes in self.scc() {
+            let idx = condensed.add_node(nodes);
+            component.extend(condensed[idx].data.iter().map(|&node| (node, idx)));
+        }
+        let mut edges: HashMap<_, EdgeIndex> = HashMap::new();
+        for edge in self.edge_indices() {
+            let [src, dst] = self.src_dst(edge).unwrap().map(|node| component[&node]);
+            if src == dst {
+                continue;
+            }
+            let idx = *edges
+                .entry((src, dst))
+                .or_insert_with(|| condensed.add_edge(src, dst, Vec::new()).unwrap());
+            condensed[idx].data.push(edge);
+        }
+        condensed
+    }
+
+    /// Edges of a path with the fewest hops from `src` to `dst`, travelling along `dir`.
+    pub fn shortest_path(
+        &self,
+        src: NodeIndex,
+        dst: NodeIndex,
+        dir: usize,
+    ) -> Option<Vec<EdgeIndex>> {
+        let mut queue = VecDeque::from([src]);
+        let mut arrival = HashMap::new();
+        while let Some(idx) = queue.pop_front() {
+            if idx == dst {
+                return Some(self.backtrack(src, dst, dir, &arrival));
+            }
+            for (neighbor, edge) in self.neighbors(idx, dir) {
+                if neighbor != src && !arrival.contains_key(&neighbor) {
+                    arrival.insert(neighbor, edge);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        None
+    }
+
+    /// Edges and total cost of a path with the least summed up weight from `src` to `dst`, travelling along `dir`.
+    /// Weights must not be negative.
+    pub fn cheapest_path(
+        &self,
+        src: NodeIndex,
+        dst: NodeIndex,
+        dir: usize,
+        weight: impl Fn(&E) -> f64,
+    ) -> Option<(f64, Vec<EdgeIndex>)> {
+        let mut heap = BinaryHeap::from([Visit(0.0, src)]);
+        let mut cost = HashMap::from([(src, 0.0)]);
+        let mut arrival = HashMap::new();
+        while let Some(Visit(total, idx)) = heap.pop() {
+            if idx == dst {
+                return Some((total, self.backtrack(src, dst, dir, &arrival)));
+            }
+            if total > cost[&idx] {
+                // Outdated entry, the node has been reached more cheaply meanwhile
+                continue;
+            }
+            for edge in self.edges(idx, dir) {
+                let neighbor = self.src_dst(edge).unwrap()[dir ^ 1];
+                let total = total + weight(&self[edge].data);
+                if cost.get(&neighbor).is_none_or(|&known| total < known) {
+                    cost.insert(neighbor, total);
+                    arrival.insert(neighbor, edge);
+                    heap.push(Visit(total, neighbor));
+                }
+            }
+        }
+        None
+    }
+
+    /// Follow the edges by which each node has been reached back from `dst` to `src`.
+    fn backtrack(
+        &self,
+        src: NodeIndex,
+        mut dst: NodeIndex,
+        dir: usize,
+        arrival: &HashMap<NodeIndex, EdgeIndex>,
+    ) -> Vec<EdgeIndex> {
+        let mut path = Vec::new();
+        while dst != src {
+            let edge = arrival[&dst];
+            path.push(edge);
+            dst = self.src_dst(edge).unwrap()[dir];
+        }
+        path.reverse();
+        path
+    }
+
+    /// Edges of every path from `src` to `dst` which visits no node twice and has at most `max_len` edges.
+    pub fn all_simple_paths(
+        &self,
+        src: NodeIndex,
+        dst: NodeIndex,
+        max_len: usize,
+    ) -> Vec<Vec<EdgeIndex>> {
+        fn walk<N, E>(
+            graph: &Graph<N, E>,
+            idx: NodeIndex,
+            dst: NodeIndex,
+            max_len: usize,
+            visited: &mut HashSet<NodeIndex>,
+            path: &mut Vec<EdgeIndex>,
+            paths: &mut Vec<Vec<EdgeIndex>>,
+        ) {
+            if idx == dst {
+                paths.push(path.clone());
+                return;
+            }
+            if path.len() == max_len {
+                return;
+            }
+            for (neighbor, edge) in graph.neighbors(idx, 0) {
+                if visited.insert(neighbor) {
+                    path.push(edge);
+                    walk(graph, neighbor, dst, max_len, visited, path, paths);
+                    path.pop();
+                    visited.remove(&neighbor);
+                }
+            }
+        }
+
+        let mut paths = Vec::new();
+        let mut visited = HashSet::from([src]);
+        walk(
+            self,
+            src,
+            dst,
+            max_len,
+            &mut visited,
+            &mut Vec::new(),
+            &mut paths,
+        );
+        paths
     }
 
     pub fn edges(&self, idx: NodeIndex, dir: usize) -> Edges<'_, E> {
@@ -249,7 +826,7 @@ impl<N, E> Graph<N, E> {
         visited.insert(idx);
 
         Bfs {
-            graph: &self,
+            graph: self,
             queue,
             visited,
             dir,
@@ -277,10 +854,42 @@ impl ScheduleInfo {
         self.stage = stage;
         self.count[dir ^ 1] += 1;
     }
+
+    pub fn stage(&self) -> usize {
+        self.stage
+    }
+
+    /// Number of edges leaving in the scheduled direction or of additional arrivals from the opposite one.
+    pub fn count(&self, dir: usize) -> usize {
+        self.count[dir]
+    }
+}
+
+/// Entry of the Dijkstra queue, ordered such that the max heap pops the cheapest node first
+struct Visit(f64, NodeIndex);
+
+impl PartialEq for Visit {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Visit {}
+
+impl PartialOrd for Visit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Visit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0).then(other.1.cmp(&self.1))
+    }
 }
 
 pub struct Edges<'a, E> {
-    edges: &'a [Result<Edge<E>, ()>],
+    edges: &'a [Slot<Edge<E>>],
     next: Option<EdgeIndex>,
     dir: usize,
 }
@@ -290,7 +899,7 @@ impl<E> Iterator for Edges<'_, E> {
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(idx) = self.next {
-            self.next = self.edges[idx.0].as_ref().unwrap().next[self.dir].ok();
+            self.next = self.edges[idx.0].unwrap().next[self.dir].ok();
             return Some(idx);
         }
         None
@@ -308,9 +917,9 @@ impl<E> Iterator for Neighbors<'_, E> {
         if let Some(edge_idx) = self.iter.next() {
             // Follow the other edge list until reaching its end, holding the node index
             let node_dir = self.iter.dir ^ 1;
-            let mut next = self.iter.edges[edge_idx.0].as_ref().unwrap().next[node_dir];
+            let mut next = self.iter.edges[edge_idx.0].unwrap().next[node_dir];
             while let Ok(next_idx) = next {
-                next = self.iter.edges[next_idx.0].as_ref().unwrap().next[node_dir];
+                next = self.iter.edges[next_idx.0].unwrap().next[node_dir];
             }
 
             return next.err().map(|node_idx| (node_idx, edge_idx));
@@ -342,3 +951,122 @@ impl<N, E> Iterator for Bfs<'_, N, E> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo random numbers, so that failures reproduce.
+    fn random(state: &mut u64) -> usize {
+        *state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (*state >> 33) as usize
+    }
+
+    /// The maintained order has every edge point forward, or the cycle is a closed walk.
+    fn check_order(graph: &mut Graph<(), ()>) {
+        match graph.toposort_mut() {
+            Ok(order) => {
+                assert_eq!(order.len(), graph.node_count());
+                let position: HashMap<_, _> = order
+                    .iter()
+                    .enumerate()
+                    .map(|(pos, &idx)| (idx, pos))
+                    .collect();
+                for edge in graph.edge_indices() {
+                    let [src, dst] = graph.src_dst(edge).unwrap();
+                    assert!(position[&src] < position[&dst]);
+                }
+            }
+            Err(cycle) => {
+                let ends: Vec<_> = cycle
+                    .iter()
+                    .map(|&edge| graph.src_dst(edge).unwrap())
+                    .collect();
+                for (edge, next) in ends.iter().zip(ends.iter().cycle().skip(1)) {
+                    assert_eq!(edge[1], next[0]);
+                }
+            }
+        }
+        assert_eq!(graph.toposort().is_ok(), graph.sort().is_ok());
+    }
+
+    #[test]
+    fn incremental_order_matches_sort() {
+        let mut graph = Graph::default();
+        let mut nodes: Vec<_> = (0..30).map(|_| graph.add_node(())).collect();
+        let mut state = 1;
+        for _ in 0..3000 {
+            match random(&mut state) % 10 {
+                0..=5 => {
+                    let [a, b] = [0; 2].map(|_| random(&mut state) % nodes.len());
+                    // Mostly forward edges, so the graph keeps switching between acyclic and cyclic
+                    let (src, dst) = match random(&mut state) % 8 {
+                        0 => (a.max(b), a.min(b)),
+                        _ => (a.min(b), a.max(b)),
+                    };
+                    graph.add_edge(nodes[src], nodes[dst], ()).unwrap();
+                }
+                6..=7 => {
+                    let edges: Vec<_> = graph.edge_indices().collect();
+                    if !edges.is_empty() {
+                        let edge = edges[random(&mut state) % edges.len()];
+                        graph.remove_edge(edge).unwrap();
+                    }
+                }
+                _ => {
+                    let pos = random(&mut state) % nodes.len();
+                    graph.remove_node(nodes[pos]).unwrap();
+                    nodes[pos] = graph.add_node(());
+                }
+            }
+            check_order(&mut graph);
+        }
+    }
+
+    #[test]
+    fn unrelated_removal_keeps_cycle() {
+        let mut graph = Graph::default();
+        let [a, b, c, d] = [(); 4].map(|_| graph.add_node(()));
+        let loop_edges = [graph.add_edge(a, b, ()), graph.add_edge(b, a, ())].map(Result::unwrap);
+        let edge = graph.add_edge(c, d, ()).unwrap();
+        let mut cycle = graph.toposort_mut().unwrap_err();
+        cycle.sort();
+        assert_eq!(cycle, loop_edges);
+        graph.remove_edge(edge).unwrap();
+        assert!(matches!(graph.sorting, Sorting::Cyclic(_)));
+        graph.remove_edge(loop_edges[0]).unwrap();
+        assert!(matches!(graph.sorting, Sorting::Stale));
+        check_order(&mut graph);
+        graph.toposort_mut().unwrap();
+        assert!(matches!(graph.sorting, Sorting::Sorted(_)));
+    }
+
+    #[test]
+    fn graph_is_shareable() {
+        // Workers of the executor borrow the graph from several threads
+        fn sync<T: Sync>() {}
+        sync::<Graph<(), ()>>();
+    }
+
+    #[test]
+    fn stale_indices_are_detected() {
+        let mut graph = Graph::default();
+        let [a, b, c] = [(); 3].map(|_| graph.add_node(()));
+        let old = graph.add_edge(a, b, ()).unwrap();
+        graph.remove_edge(old).unwrap();
+        // The new edge recycles the slot of the removed one
+        let new = graph.add_edge(b, c, ()).unwrap();
+        assert_eq!(new.0, old.0);
+        assert!(matches!(graph.edge(old), Err(GraphError::StaleEdge(idx)) if idx == old));
+        assert_eq!(graph.remove_edge(old), Err(GraphError::StaleEdge(old)));
+        assert_eq!(graph.src_dst(new).unwrap(), [b, c]);
+
+        graph.remove_node(a).unwrap();
+        let d = graph.add_node(());
+        assert_eq!(d.0, a.0);
+        assert_eq!(graph.remove_node(a), Err(GraphError::StaleNode(a)));
+        assert_eq!(graph.neighbors(b, 0).collect::<Vec<_>>(), [(c, new)]);
+    }
+}
@@ -1,209 +1,1539 @@
-use std::{collections::HashMap, fmt::Debug, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Debug, Write},
+    fs, io, mem,
+    path::Path,
+    rc::Rc,
+    thread,
+};
 
-use crate::graph::{EdgeIndex, Graph, NodeIndex};
+use crate::{
+    command::{self, end, quote},
+    eval::{self, Output},
+    executor::{Executor, NodeProcessor},
+    graph::{EdgeIndex, Graph, GraphError, NodeIndex, ScheduleInfo},
+    undo::{Undo, UndoOp},
+};
+
+#[derive(Debug)]
+pub enum HiveError {
+    UnknownNode(String),
+    UnknownEdge(usize),
+    UnknownState(usize),
+    UnknownAttr(String),
+    DuplicateNode(String),
+    NegativeWeight(usize),
+    UnknownOperator(String),
+    /// A node lacks the port, as it declares others
+    UnknownPort(String, String),
+    /// Edges of a node need to specify one of the ports it declares
+    MissingPort(String),
+    /// An edge is attached to a port or node which a declaration leaves out
+    PortInUse(usize, String),
+    UnknownType(String),
+    /// A type can't change while the node is of it
+    TypeInUse(String, String),
+    /// A port of a typed node has reached the most edges its type allows
+    PortFull(String, usize),
+    /// Connected ports of typed nodes carry different kinds of values
+    KindMismatch([(String, String); 2]),
+    /// Evaluating a node failed
+    Eval(String),
+    /// A number given by the user exceeds the addressable range
+    IndexOverflow(String),
+    /// The hive and its undo history disagree about the state of the graph
+    InvariantViolation(String),
+    Graph(GraphError),
+    /// A cycle rendered in pipe notation
+    Cycle(String),
+    Parse(String),
+    Io(io::Error),
+    /// Wraps an error with a likely meant name
+    Suggestion(Box<HiveError>, String),
+    /// Wraps an error with the line of the file it originates from
+    Line(usize, Box<HiveError>),
+}
+
+impl fmt::Display for HiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HiveError::UnknownNode(node) => write!(f, "unknown node `{node}`"),
+            HiveError::UnknownEdge(edge) => write!(f, "unknown edge {edge}"),
+            HiveError::UnknownState(state) => write!(f, "unknown undo state {state}"),
+            HiveError::UnknownAttr(attr) => write!(f, "unknown attribute {attr}"),
+            HiveError::DuplicateNode(node) => write!(f, "node `{node}` already exists"),
+            HiveError::NegativeWeight(edge) => write!(f, "edge {edge} has a negative weight"),
+            HiveError::UnknownOperator(op) => write!(f, "unknown operator {op}"),
+            HiveError::UnknownPort(node, port) => {
+                write!(f, "unknown port `{}`", end(node, Some(port)))
+            }
+            HiveError::MissingPort(node) => {
+                write!(f, "edges of node `{node}` need to specify one of its ports")
+            }
+            HiveError::PortInUse(edge, end) => {
+                write!(
+                    f,
+                    "edge {edge} is attached to `{end}`, which isn't declared"
+                )
+            }
+            HiveError::UnknownType(name) => write!(f, "unknown type `{name}`"),
+            HiveError::TypeInUse(name, node) => {
+                write!(f, "type `{name}` is in use by node `{node}`")
+            }
+            HiveError::PortFull(end, max) => {
+                let edges = if *max == 1 { "edge" } else { "edges" };
+                write!(f, "port `{end}` takes at most {max} {edges}")
+            }
+            HiveError::KindMismatch([(src, src_kind), (dst, dst_kind)]) => write!(
+                f,
+                "can't connect `{src}` of kind {src_kind} to `{dst}` of kind {dst_kind}"
+            ),
+            HiveError::Eval(reason) => write!(f, "{reason}"),
+            HiveError::IndexOverflow(number) => write!(f, "number {number} is too large"),
+            HiveError::InvariantViolation(reason) => write!(f, "invariant violation: {reason}"),
+            HiveError::Graph(error) => write!(f, "{error}"),
+            HiveError::Cycle(cycle) => write!(f, "cycle {cycle}"),
+            HiveError::Parse(error) => write!(f, "{error}"),
+            HiveError::Io(error) => write!(f, "{error}"),
+            HiveError::Suggestion(error, suggestion) => {
+                write!(f, "{error}, did you mean `{suggestion}`?")
+            }
+            HiveError::Line(line, error) => write!(f, "line {line}: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for HiveError {}
+
+impl From<GraphError> for HiveError {
+    fn from(error: GraphError) -> Self {
+        HiveError::Graph(error)
+    }
+}
+
+impl From<io::Error> for HiveError {
+    fn from(error: io::Error) -> Self {
+        HiveError::Io(error)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{number}"),
+            Value::Text(text) => write!(f, "{}", quote(text)),
+        }
+    }
+}
+
+pub type Attrs = BTreeMap<String, Value>;
+
+/// Owner of attributes, addressing edges by their number
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    Node(String),
+    Edge(usize),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Node(node) => write!(f, "{}", quote(node)),
+            Target::Edge(edge) => write!(f, "{edge}"),
+        }
+    }
+}
+
+/// Change of a hive reported to its observers, for direct actions as well as undo and redo
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    NodeAdded(String),
+    NodeRemoved(String),
+    NodeRenamed {
+        old: String,
+        new: String,
+    },
+    EdgeAdded {
+        src: String,
+        dst: String,
+        ports: [Option<String>; 2],
+        id: usize,
+    },
+    EdgeRemoved {
+        src: String,
+        dst: String,
+        ports: [Option<String>; 2],
+        id: usize,
+    },
+    /// A type got defined or with `None` removed
+    TypeDefined {
+        name: String,
+        node_type: Option<NodeType>,
+    },
+    /// A node got a type or with `None` lost it
+    NodeTyped {
+        node: String,
+        node_type: Option<String>,
+    },
+    /// The ports of a node got declared or with `None` lifted, outgoing for `dir` 0 and incoming for 1
+    PortsChanged {
+        node: String,
+        dir: usize,
+        ports: Option<Vec<String>>,
+    },
+    /// An attribute got set or with `None` removed
+    AttrChanged {
+        target: Target,
+        key: String,
+        value: Option<Value>,
+    },
+    /// The state with the given id has been undone after undoing its changes
+    UndoApplied(usize),
+    /// The state with the given id has been redone after redoing its changes
+    RedoApplied(usize),
+    Cleared,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::NodeAdded(node) => write!(f, "added node {}", quote(node)),
+            Event::NodeRemoved(node) => write!(f, "removed node {}", quote(node)),
+            Event::NodeRenamed { old, new } => {
+                write!(f, "renamed node {} to {}", quote(old), quote(new))
+            }
+            Event::EdgeAdded {
+                src,
+                dst,
+                ports: [src_port, dst_port],
+                id,
+            } => write!(
+                f,
+                "added edge {} > {}|{id}",
+                end(src, src_port.as_deref()),
+                end(dst, dst_port.as_deref())
+            ),
+            Event::EdgeRemoved {
+                src,
+                dst,
+                ports: [src_port, dst_port],
+                id,
+            } => write!(
+                f,
+                "removed edge {} > {}|{id}",
+                end(src, src_port.as_deref()),
+                end(dst, dst_port.as_deref())
+            ),
+            Event::PortsChanged { node, dir, ports } => write!(
+                f,
+                "ports {} {} {}",
+                quote(node),
+                DIR_STR[*dir],
+                command::port_list(ports.as_deref())
+            ),
+            Event::AttrChanged {
+                target,
+                key,
+                value: Some(value),
+            } => write!(f, "set {target}.{key} = {value}"),
+            Event::AttrChanged {
+                target,
+                key,
+                value: None,
+            } => write!(f, "unset {target}.{key}"),
+            Event::TypeDefined {
+                name,
+                node_type: Some(node_type),
+            } => write!(f, "type {name} {node_type}"),
+            Event::TypeDefined {
+                name,
+                node_type: None,
+            } => write!(f, "untype {name}"),
+            Event::NodeTyped { node, node_type } => {
+                write!(
+                    f,
+                    "{}: {}",
+                    quote(node),
+                    node_type.as_deref().unwrap_or("*")
+                )
+            }
+            Event::UndoApplied(id) => write!(f, "undone state {id}"),
+            Event::RedoApplied(id) => write!(f, "redone state {id}"),
+            Event::Cleared => write!(f, "cleared"),
+        }
+    }
+}
+
+/// Port of a node type with the kind of values passing it and optionally the most edges it takes
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortSchema {
+    pub name: String,
+    pub kind: String,
+    pub max: Option<usize>,
+}
+
+impl fmt::Display for PortSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.kind)?;
+        match self.max {
+            Some(max) => write!(f, "[{max}]"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Outgoing and incoming ports which nodes of a type get declared
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NodeType {
+    pub ports: [Vec<PortSchema>; 2],
+}
+
+impl fmt::Display for NodeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (dir, ports) in self.ports.iter().enumerate() {
+            let ports: Vec<_> = ports.iter().map(PortSchema::to_string).collect();
+            let space = if dir > 0 { " " } else { "" };
+            write!(f, "{space}{} {{{}}}", DIR_STR[dir], ports.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+type Observer = Box<dyn FnMut(&Event)>;
+
+/// Pipe notation of the outgoing and the incoming side
+pub(crate) const DIR_STR: [&str; 2] = [">", "<"];
+
+/// Port of a node, or `None` for the node itself, along with the nodes, ports and numbers of the edges attached to it
+pub type PortEdges<'a> = (Option<&'a str>, Vec<(&'a str, Option<&'a str>, usize)>);
+
+struct NodeData {
+    name: String,
+    attrs: Attrs,
+    /// Declared outgoing and incoming ports, where `None` accepts edges at any port
+    ports: [Option<Vec<String>>; 2],
+    node_type: Option<String>,
+}
+
+/// Edge between a port of its source and a port of its destination, or the nodes themselves for `None`
+#[derive(Clone)]
+struct EdgeData {
+    ports: [Option<String>; 2],
+    attrs: Attrs,
+}
+
+impl NodeProcessor for NodeData {
+    type Output = Output;
+
+    fn process(&self, inputs: &[Output]) -> Output {
+        eval::operate(&self.name, &self.attrs, inputs)
+    }
+}
 
 #[derive(Default)]
 pub struct Hive {
-    graph: Graph<(), ()>,
+    graph: Graph<NodeData, EdgeData>,
     nodes: HashMap<String, NodeIndex>,
     pub undo: Undo,
+    /// Cached results of evaluated nodes, which are outdated for the dirty nodes of the graph
+    outputs: HashMap<NodeIndex, Output>,
+    observers: Vec<Observer>,
+    /// Registry of the node types by name
+    types: BTreeMap<String, NodeType>,
 }
 
-#[derive(Default)]
-pub struct Undo {
-    history: Vec<Rc<dyn Fn(&mut Hive)>>,
-    pos: usize,
-    pause: bool,
-}
+impl Hive {
+    /// Register a callback which receives every change of the hive from now on.
+    pub fn subscribe(&mut self, observer: impl FnMut(&Event) + 'static) {
+        self.observers.push(Box::new(observer));
+    }
 
-impl Undo {
-    fn track<F>(&mut self, action: F)
-    where
-        F: Fn(&mut Hive) + 'static,
-    {
-        if self.pause {
-            // FIXME: On pause we get an action just to drop it right away. Can we avoid its construction in the first place?
-            return;
+    fn emit(&mut self, event: Event) {
+        for observer in &mut self.observers {
+            observer(&event);
         }
-        if self.pos == self.history.len() {
-            self.pos = self.history.len() + 1
+    }
+
+    /// Apply changes as a whole: on success they are piled into a single undo state,
+    /// on error they are rolled back and vanish from the history.
+    pub fn transaction<T>(
+        &mut self,
+        changes: impl FnOnce(&mut Hive) -> Result<T, HiveError>,
+    ) -> Result<T, HiveError> {
+        let snapshot = self.undo.snapshot();
+        let (current, next) = (self.undo.current, self.undo.states[self.undo.current].next);
+        match changes(self) {
+            Ok(value) => {
+                self.undo.pile(snapshot);
+                Ok(value)
+            }
+            Err(error) => {
+                // The new states form a chain below the former current state, which is undone in reverse
+                for id in (snapshot..self.undo.states.len()).rev() {
+                    let op = self.undo.states[id].op.inverse();
+                    if let Err(rollback) = self.replay(&op) {
+                        // Only the undone states get dropped, so the history still matches the hive
+                        self.undo.states.truncate(id + 1);
+                        self.undo.states[id].next = None;
+                        self.undo.current = id;
+                        return Err(HiveError::InvariantViolation(format!(
+                            "rolling back after `{error}` failed: {rollback}"
+                        )));
+                    }
+                }
+                self.undo.states.truncate(snapshot);
+                self.undo.states[current].next = next;
+                self.undo.current = current;
+                Err(error)
+            }
         }
-        self.history.push(Rc::new(action));
     }
 
-    fn reset(&mut self) {
-        self.pos = self.history.len();
+    /// Connect two nodes, optionally at an outgoing port of `src` and an incoming port of `dst`.
+    /// Edges violating the ports of the nodes or their types are rejected before anything changes.
+    pub fn pipe(
+        &mut self,
+        src: &str,
+        dst: &str,
+        ports: [Option<&str>; 2],
+    ) -> Result<(), HiveError> {
+        self.check_edge([src, dst], ports)?;
+        self.transaction(|hive| {
+            hive.add_node(src);
+            hive.add_node(dst);
+            let ports = ports.map(|port| port.map(str::to_string));
+            hive.add_edge(src, dst, ports).map(drop)
+        })
     }
 
-    pub fn snapshot(&self) -> usize {
-        self.history.len()
+    pub fn delete_edge(&mut self, idx: usize) -> Result<(), HiveError> {
+        let edge = self
+            .graph
+            .edge_index(idx)
+            .ok_or(HiveError::UnknownEdge(idx))?;
+        self.transaction(|hive| hive.remove_edge(edge))
     }
 
-    pub fn pile(&mut self, snapshot: usize) {
-        if self.history.len() < 2 || self.snapshot().saturating_sub(snapshot) < 2 {
-            // Building a pile with less then 2 elements equals doing nothing here.
-            return;
+    /// Remove all edges from `src` to `dst`, where `None` matches any node.
+    pub fn unpipe(&mut self, src: Option<&str>, dst: Option<&str>) -> Result<(), HiveError> {
+        let [src, dst] = [src, dst].map(|node| node.map(|node| self.node(node)).transpose());
+        let ends = [src?, dst?];
+        let edges: Vec<_> = self
+            .graph
+            .edge_indices()
+            .filter(|&edge| {
+                let src_dst = self.graph.src_dst(edge).unwrap();
+                (0..2).all(|dir| ends[dir].is_none_or(|end| end == src_dst[dir]))
+            })
+            .collect();
+        self.transaction(|hive| {
+            edges
+                .into_iter()
+                .rev()
+                .try_for_each(|edge| hive.remove_edge(edge))
+        })
+    }
+
+    /// Set or with `None` remove an attribute of a node or edge.
+    pub fn set_attr(
+        &mut self,
+        target: &Target,
+        key: &str,
+        value: Option<Value>,
+    ) -> Result<(), HiveError> {
+        let attrs = self.attrs_mut(target)?;
+        let old = match &value {
+            Some(value) => attrs.insert(key.to_string(), value.clone()),
+            None => attrs
+                .remove(key)
+                .map(Some)
+                .ok_or_else(|| HiveError::UnknownAttr(format!("{target}.{key}")))?,
+        };
+        if old != value {
+            if let Target::Node(node) = target {
+                self.graph.touch(self.node(node)?);
+            }
+            self.undo.track(UndoOp::SetAttr {
+                target: target.clone(),
+                key: key.to_string(),
+                old,
+                new: value.clone(),
+            });
+            self.emit(Event::AttrChanged {
+                target: target.clone(),
+                key: key.to_string(),
+                value,
+            });
         }
-        assert!(!self.pause);
+        Ok(())
+    }
+
+    pub fn attrs(&self, target: &Target) -> Result<&Attrs, HiveError> {
+        Ok(match target {
+            Target::Node(node) => &self.graph[self.node(node)?].data().attrs,
+            Target::Edge(edge) => &self.graph[self.edge(*edge)?].data().attrs,
+        })
+    }
 
-        let pile = self.history.split_off(snapshot);
-        self.history.push(Rc::new(move |hive| {
-            let snapshot = hive.undo.snapshot();
-            for action in pile.iter().rev() {
-                action(hive)
+    fn attrs_mut(&mut self, target: &Target) -> Result<&mut Attrs, HiveError> {
+        Ok(match target {
+            Target::Node(node) => {
+                let idx = self.node(node)?;
+                &mut self.graph[idx].data_mut().attrs
             }
-            hive.undo.pile(snapshot);
-        }));
-        self.reset();
+            Target::Edge(edge) => {
+                let idx = self.edge(*edge)?;
+                &mut self.graph[idx].data_mut().attrs
+            }
+        })
     }
-}
 
-impl Hive {
-    pub fn pipe(&mut self, src: &str, dst: &str) {
-        let src = *self.add_node(src);
-        let dst = *self.add_node(dst);
-        self.add_edge(src, dst);
+    /// Declare the outgoing ports of a node for `dir` 0 or its incoming ports for 1, restricting its edges to them.
+    /// With `None` the node accepts edges at any port or none again.
+    pub fn set_ports(
+        &mut self,
+        node: &str,
+        dir: usize,
+        ports: Option<Vec<String>>,
+    ) -> Result<(), HiveError> {
+        let idx = self.node(node)?;
+        if let Some(declared) = &ports {
+            for edge in self.graph.edges(idx, dir) {
+                let port = &self.graph[edge].data().ports[dir];
+                if !port.as_ref().is_some_and(|port| declared.contains(port)) {
+                    let end = end(node, port.as_deref());
+                    return Err(HiveError::PortInUse(edge.0, end));
+                }
+            }
+        }
+        let old = std::mem::replace(&mut self.graph[idx].data_mut().ports[dir], ports.clone());
+        if old != ports {
+            self.undo.track(UndoOp::SetPorts {
+                node: node.to_string(),
+                dir,
+                old,
+                new: ports.clone(),
+            });
+            self.emit(Event::PortsChanged {
+                node: node.to_string(),
+                dir,
+                ports,
+            });
+        }
+        Ok(())
+    }
+
+    /// Edges at each port of a node along with the node and port at their other end, outgoing for `dir` 0 and incoming for 1.
+    /// Declared ports come in their order even when unconnected, otherwise the node itself comes first as port `None`.
+    pub fn ports(&self, node: &str, dir: usize) -> Result<Vec<PortEdges<'_>>, HiveError> {
+        let idx = self.node(node)?;
+        let declared = &self.graph[idx].data().ports[dir];
+        let mut ports: Vec<PortEdges> = declared
+            .iter()
+            .flatten()
+            .map(|port| (Some(port.as_str()), Vec::new()))
+            .collect();
+        for edge in self.graph.edges(idx, dir) {
+            let data = self.graph[edge].data();
+            let other = self.graph.src_dst(edge)?[1 - dir];
+            let port = data.ports[dir].as_deref();
+            let other = (self.name(other), data.ports[1 - dir].as_deref(), edge.0);
+            match ports.iter_mut().find(|(existing, _)| *existing == port) {
+                Some((_, edges)) => edges.push(other),
+                None => ports.push((port, vec![other])),
+            }
+        }
+        if declared.is_none() {
+            ports.sort_by_key(|&(port, _)| port);
+        }
+        Ok(ports)
+    }
+
+    /// Define a type by name or with `None` remove it, as long as no node is of that type.
+    pub fn define_type(
+        &mut self,
+        name: &str,
+        node_type: Option<NodeType>,
+    ) -> Result<(), HiveError> {
+        if node_type.is_none() && !self.types.contains_key(name) {
+            return Err(HiveError::UnknownType(name.to_string()));
+        }
+        let typed = self
+            .graph
+            .node_indices()
+            .find(|&idx| self.graph[idx].data().node_type.as_deref() == Some(name));
+        if let Some(idx) = typed {
+            let node = self.name(idx).to_string();
+            return Err(HiveError::TypeInUse(name.to_string(), node));
+        }
+        let old = match &node_type {
+            Some(node_type) => self.types.insert(name.to_string(), node_type.clone()),
+            None => self.types.remove(name),
+        };
+        if old != node_type {
+            self.undo.track(UndoOp::DefineType {
+                name: name.to_string(),
+                old,
+                new: node_type.clone(),
+            });
+            self.emit(Event::TypeDefined {
+                name: name.to_string(),
+                node_type,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn type_names(&self) -> impl Iterator<Item = &str> {
+        self.types.keys().map(String::as_str)
+    }
+
+    /// Give a node a type, creating the node if necessary, and declare the ports of the type on it.
+    /// With `None` the node loses its type but keeps the declared ports.
+    pub fn set_type(&mut self, node: &str, node_type: Option<&str>) -> Result<(), HiveError> {
+        let Some(name) = node_type else {
+            self.node(node)?;
+            return self.retype(node, None);
+        };
+        let ports = self
+            .types
+            .get(name)
+            .ok_or_else(|| HiveError::UnknownType(name.to_string()))?
+            .ports
+            .clone();
+        self.transaction(|hive| {
+            let idx = hive.add_node(node);
+            hive.retype(node, Some(name))?;
+            for (dir, ports) in ports.into_iter().enumerate() {
+                let ports = ports.into_iter().map(|port| port.name).collect();
+                hive.set_ports(node, dir, Some(ports))?;
+            }
+            hive.check_node(idx)
+        })
+    }
+
+    /// Change the type of a node without touching its ports.
+    fn retype(&mut self, node: &str, node_type: Option<&str>) -> Result<(), HiveError> {
+        let idx = self.node(node)?;
+        let new = node_type.map(str::to_string);
+        let old = std::mem::replace(&mut self.graph[idx].data_mut().node_type, new.clone());
+        if old != new {
+            self.undo.track(UndoOp::SetType {
+                node: node.to_string(),
+                old,
+                new: new.clone(),
+            });
+            self.emit(Event::NodeTyped {
+                node: node.to_string(),
+                node_type: new,
+            });
+        }
+        Ok(())
+    }
+
+    /// Port of the type of a node, if the node has a type listing the port.
+    fn schema(&self, idx: NodeIndex, dir: usize, port: &str) -> Option<&PortSchema> {
+        let node_type = self.graph[idx].data().node_type.as_ref()?;
+        self.types[node_type].ports[dir]
+            .iter()
+            .find(|schema| schema.name == port)
+    }
+
+    /// Make sure a new edge fits the declared ports of its nodes as well as the kinds and cardinalities of their types.
+    /// Nodes yet to be created accept any edge.
+    fn check_edge(&self, ends: [&str; 2], ports: [Option<&str>; 2]) -> Result<(), HiveError> {
+        let ends = ends.map(|node| self.nodes.get(node).copied());
+        for dir in 0..2 {
+            let Some(idx) = ends[dir] else {
+                continue;
+            };
+            self.check_port(idx, dir, ports[dir])?;
+            let Some(port) = ports[dir] else {
+                continue;
+            };
+            if let Some(max) = self.schema(idx, dir, port).and_then(|schema| schema.max) {
+                let count = self
+                    .graph
+                    .edges(idx, dir)
+                    .filter(|&edge| self.graph[edge].data().ports[dir].as_deref() == Some(port))
+                    .count();
+                if count >= max {
+                    return Err(HiveError::PortFull(end(self.name(idx), Some(port)), max));
+                }
+            }
+        }
+        match ends {
+            [Some(src), Some(dst)] => self.check_kinds([src, dst], ports),
+            _ => Ok(()),
+        }
+    }
+
+    /// Make sure the edges of a node respect the kinds and cardinalities of its type.
+    fn check_node(&self, idx: NodeIndex) -> Result<(), HiveError> {
+        for dir in 0..2 {
+            let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+            for edge in self.graph.edges(idx, dir) {
+                let data = self.graph[edge].data();
+                let ports = [0, 1].map(|dir| data.ports[dir].as_deref());
+                self.check_kinds(self.graph.src_dst(edge)?, ports)?;
+                if let Some(port) = ports[dir] {
+                    *counts.entry(port).or_default() += 1;
+                }
+            }
+            for (port, count) in counts {
+                if let Some(max) = self.schema(idx, dir, port).and_then(|schema| schema.max) {
+                    if count > max {
+                        return Err(HiveError::PortFull(end(self.name(idx), Some(port)), max));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Make sure both ends of an edge carry the same kind, given both of them are ports of typed nodes.
+    fn check_kinds(&self, ends: [NodeIndex; 2], ports: [Option<&str>; 2]) -> Result<(), HiveError> {
+        let schemas = [0, 1].map(|dir| self.schema(ends[dir], dir, ports[dir]?));
+        let [Some(src), Some(dst)] = schemas else {
+            return Ok(());
+        };
+        if src.kind == dst.kind {
+            return Ok(());
+        }
+        Err(HiveError::KindMismatch([0, 1].map(|dir| {
+            let end = end(self.name(ends[dir]), ports[dir]);
+            (end, [src, dst][dir].kind.clone())
+        })))
+    }
+
+    /// Make sure a node accepts an edge at the port, which has to be one of its declared ports if there are any.
+    fn check_port(&self, idx: NodeIndex, dir: usize, port: Option<&str>) -> Result<(), HiveError> {
+        let Some(declared) = &self.graph[idx].data().ports[dir] else {
+            return Ok(());
+        };
+        match port {
+            Some(port) if declared.iter().any(|declared| declared == port) => Ok(()),
+            Some(port) => Err(HiveError::UnknownPort(
+                self.name(idx).to_string(),
+                port.to_string(),
+            )),
+            None => Err(HiveError::MissingPort(self.name(idx).to_string())),
+        }
+    }
+
+    /// Assign an operator to a node for evaluation, along with the value of constants.
+    pub fn set_operator(
+        &mut self,
+        node: &str,
+        op: &str,
+        value: Option<f64>,
+    ) -> Result<(), HiveError> {
+        if !eval::OPERATORS.contains(&op) {
+            return Err(HiveError::UnknownOperator(op.to_string()));
+        }
+        let target = Target::Node(node.to_string());
+        self.transaction(|hive| {
+            hive.set_attr(&target, "op", Some(Value::Text(op.to_string())))?;
+            match value {
+                Some(value) => hive.set_attr(&target, "value", Some(Value::Number(value))),
+                None => Ok(()),
+            }
+        })
+    }
+
+    /// Evaluate all nodes which aren't cached yet, running independent nodes in parallel.
+    /// Yields the names and values of all `print` nodes in topological order.
+    pub fn eval(&mut self) -> Result<Vec<(&str, f64)>, HiveError> {
+        self.execute(None)?;
+        let mut printed = Vec::new();
+        for idx in self.graph.toposort().unwrap() {
+            let node = self.graph[idx].data();
+            if node.attrs.get("op") == Some(&Value::Text("print".to_string())) {
+                let value = self.outputs[&idx].clone().map_err(HiveError::Eval)?;
+                printed.push((node.name.as_str(), value));
+            }
+        }
+        Ok(printed)
+    }
+
+    /// Evaluate a single node along with its upstream, leaving the rest of the graph aside.
+    pub fn value(&mut self, node: &str) -> Result<f64, HiveError> {
+        let idx = self.node(node)?;
+        self.execute(Some(idx))?;
+        self.outputs[&idx].clone().map_err(HiveError::Eval)
     }
 
-    pub fn delete_edge(&mut self, idx: usize) {
-        self.remove_edge(EdgeIndex(idx));
+    /// Drop the outputs of changed nodes and compute those of the given node and its upstream, or of all nodes.
+    fn execute(&mut self, node: Option<NodeIndex>) -> Result<(), HiveError> {
+        for idx in self.graph.dirty_nodes() {
+            self.outputs.remove(&idx);
+        }
+        self.graph.clean();
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        let executor = Executor::new(threads);
+        // Sorting once here spares the executor sorting a changed cycle from scratch on every run
+        let result = self.graph.toposort_mut().and_then(|_| match node {
+            Some(idx) => executor.run_nodes(&self.graph, self.graph.bfs(idx, 1), &mut self.outputs),
+            None => executor.run(&self.graph, &mut self.outputs),
+        });
+        match result {
+            Ok(()) => Ok(()),
+            Err(cycle) => Err(self.cycle(cycle)?),
+        }
     }
 
-    pub fn undo(&mut self, n: usize) {
+    /// Remove all attributes one by one, so undo is able to restore each of them.
+    fn clear_attrs(&mut self, target: &Target) -> Result<(), HiveError> {
+        let keys: Vec<_> = self.attrs(target)?.keys().cloned().collect();
+        for key in keys {
+            self.set_attr(target, &key, None)?;
+        }
+        Ok(())
+    }
+
+    pub fn undo(&mut self, n: usize) -> Result<(), HiveError> {
         for i in 0..n {
-            if let Some(pos) = self.undo.pos.checked_sub(1) {
-                self.undo.history[pos].clone()(self);
-                self.undo.pos = pos;
-            } else {
+            if self.undo.current == 0 {
                 let left = n - i;
                 println!("{left} undo{} ignored", if left > 1 { "'s" } else { "" });
-                return;
+                break;
             }
+            self.step_up()?;
         }
+        Ok(())
     }
 
-    pub fn redo(&mut self, n: usize) {
-        // While "redoing" we want to ignore all the implicitly incoming undo of the redo actions!
-        self.undo.pause = true;
+    pub fn redo(&mut self, n: usize) -> Result<(), HiveError> {
         for i in 0..n {
-            let pos = self.undo.pos + 1;
-            if pos < self.undo.history.len() {
-                // The most recent undo of an undo aka redo is being tracked at the end of the history!
-                // Its origin is still available at idx and has the same effect. Therefore we can remove
-                // the redo from the history here.
-                self.undo.history.pop().unwrap()(self);
-                self.undo.pos = pos;
-            } else {
-                let left = n - i;
-                println!("{left} redo{} ignored", if left > 1 { "'s" } else { "" });
-                break;
+            match self.undo.states[self.undo.current].next {
+                Some(id) => self.step_down(id)?,
+                None => {
+                    let left = n - i;
+                    println!("{left} redo{} ignored", if left > 1 { "'s" } else { "" });
+                    break;
+                }
             }
         }
+        Ok(())
+    }
+
+    /// Travel through the undo tree to any state, undoing up to the common ancestor and redoing from there.
+    pub fn goto(&mut self, id: usize) -> Result<(), HiveError> {
+        if id > self.undo.last() {
+            return Err(HiveError::UnknownState(id));
+        }
+        let path = self.undo.path(id);
+        while !path.contains(&self.undo.current) {
+            self.step_up()?;
+        }
+        let pos = path.iter().position(|&id| id == self.undo.current).unwrap();
+        for &id in &path[pos + 1..] {
+            self.step_down(id)?;
+        }
+        Ok(())
+    }
+
+    /// Apply an op of the history without tracking it once more.
+    fn replay(&mut self, op: &UndoOp) -> Result<(), HiveError> {
+        // While replaying we want to ignore all the implicitly incoming ops!
+        self.undo.pause = true;
+        let result = self.apply(op);
         self.undo.pause = false;
+        result
     }
 
-    pub fn clear(&mut self) {
-        while let Some(pos) = self.undo.pos.checked_sub(1) {
-            self.undo.history[pos].clone()(self);
-            self.undo.pos = pos;
+    fn apply(&mut self, op: &UndoOp) -> Result<(), HiveError> {
+        match op {
+            UndoOp::AddNode(node) => {
+                self.add_node(node);
+            }
+            UndoOp::RemoveNode(node) => self.remove_node(node)?,
+            UndoOp::RenameNode { old, new } => self.rename_node(old, new)?,
+            UndoOp::AddEdge {
+                edge,
+                src,
+                dst,
+                ports,
+            } => {
+                // Undo replays the exact reverse of what happened, so freed slots get recycled in the same order
+                let idx = self.add_edge(src, dst, ports.clone())?;
+                if idx.0 != *edge {
+                    return Err(HiveError::InvariantViolation(format!(
+                        "edge {src} > {dst} got recreated as {} instead of {edge}",
+                        idx.0
+                    )));
+                }
+            }
+            UndoOp::RemoveEdge { edge, .. } => self.delete_edge(*edge)?,
+            UndoOp::SetAttr {
+                target,
+                key,
+                old,
+                new,
+            } => {
+                if self.attrs(target)?.get(key) != old.as_ref() {
+                    return Err(HiveError::InvariantViolation(format!(
+                        "attribute {target}.{key} differs from its recorded value"
+                    )));
+                }
+                self.set_attr(target, key, new.clone())?;
+            }
+            UndoOp::SetPorts {
+                node,
+                dir,
+                old,
+                new,
+            } => {
+                if self.graph[self.node(node)?].data().ports[*dir] != *old {
+                    return Err(HiveError::InvariantViolation(format!(
+                        "ports of node {node} differ from their recorded declaration"
+                    )));
+                }
+                self.set_ports(node, *dir, new.clone())?;
+            }
+            UndoOp::DefineType { name, old, new } => {
+                if self.types.get(name) != old.as_ref() {
+                    return Err(HiveError::InvariantViolation(format!(
+                        "type {name} differs from its recorded definition"
+                    )));
+                }
+                self.define_type(name, new.clone())?;
+            }
+            UndoOp::SetType { node, old, new } => {
+                if self.graph[self.node(node)?].data().node_type != *old {
+                    return Err(HiveError::InvariantViolation(format!(
+                        "type of node {node} differs from its recorded one"
+                    )));
+                }
+                self.retype(node, new.as_deref())?;
+            }
+            UndoOp::Pile(ops) => ops.iter().try_for_each(|op| self.apply(op))?,
         }
-        assert!(self.graph.edges.iter().all(Result::is_err));
-        self.graph.edges.clear();
-        assert!(self.graph.nodes.iter().all(Result::is_err));
-        self.graph.nodes.clear();
-        assert!(self.nodes.is_empty());
-        self.undo.history.clear();
+        Ok(())
     }
 
-    fn add_node(&mut self, node: &str) -> &NodeIndex {
-        self.nodes.entry(node.to_string()).or_insert_with(|| {
-            self.undo.track({
-                let node = node.to_string();
-                move |hive: &mut Hive| assert!(hive.remove_node(&node))
-            });
-            self.graph.add_node(())
-        })
+    fn step_up(&mut self) -> Result<(), HiveError> {
+        let id = self.undo.current;
+        let state = &self.undo.states[id];
+        let parent = state.parent;
+        self.replay(&state.op.inverse())?;
+        self.undo.states[parent].next = Some(id);
+        self.undo.current = parent;
+        self.emit(Event::UndoApplied(id));
+        Ok(())
+    }
+
+    fn step_down(&mut self, id: usize) -> Result<(), HiveError> {
+        let op = self.undo.states[id].op.clone();
+        self.replay(&op)?;
+        self.undo.current = id;
+        self.emit(Event::RedoApplied(id));
+        Ok(())
     }
 
-    pub fn remove_node(&mut self, node: &str) -> bool {
-        if let Some(idx) = self.nodes.remove(node) {
+    pub fn clear(&mut self) -> Result<(), HiveError> {
+        while self.undo.current != 0 {
+            self.step_up()?;
+        }
+        if self.graph.node_count() + self.graph.edge_count() > 0 || !self.nodes.is_empty() {
+            return Err(HiveError::InvariantViolation(
+                "undoing the whole history left a non-empty graph".to_string(),
+            ));
+        }
+        self.graph.clear();
+        self.undo = Undo::default();
+        self.outputs.clear();
+        self.emit(Event::Cleared);
+        Ok(())
+    }
+
+    fn add_node(&mut self, node: &str) -> NodeIndex {
+        if let Some(&idx) = self.nodes.get(node) {
+            return idx;
+        }
+        self.undo.track(UndoOp::AddNode(node.to_string()));
+        let idx = self.graph.add_node(NodeData {
+            name: node.to_string(),
+            attrs: Attrs::new(),
+            ports: [None, None],
+            node_type: None,
+        });
+        self.nodes.insert(node.to_string(), idx);
+        self.emit(Event::NodeAdded(node.to_string()));
+        idx
+    }
+
+    pub fn remove_node(&mut self, node: &str) -> Result<(), HiveError> {
+        self.transaction(|hive| {
+            hive.clear_attrs(&Target::Node(node.to_string()))?;
+            hive.retype(node, None)?;
+            for dir in 0..2 {
+                hive.set_ports(node, dir, None)?;
+            }
+            let idx = hive.node(node)?;
             let mut edges = (0..2)
-                .flat_map(|dir| self.graph.edges(idx, dir))
+                .flat_map(|dir| hive.graph.edges(idx, dir))
                 .collect::<Vec<_>>();
             edges.sort();
+            // Loops show up in both directions
+            edges.dedup();
             for edge in edges.into_iter().rev() {
-                self.remove_edge(edge);
+                hive.remove_edge(edge)?;
             }
-            self.graph.remove_node_unchecked(idx);
-            self.undo.track({
-                let node = node.to_string();
-                move |hive| {
-                    hive.add_node(&node);
+            hive.outputs.remove(&idx);
+            hive.graph.remove_node(idx)?;
+            hive.nodes.remove(node);
+            hive.undo.track(UndoOp::RemoveNode(node.to_string()));
+            hive.emit(Event::NodeRemoved(node.to_string()));
+            Ok(())
+        })
+    }
+
+    /// Give a node another name while keeping its index, edges and attributes.
+    pub fn rename_node(&mut self, old: &str, new: &str) -> Result<(), HiveError> {
+        if old == new {
+            return self.node(old).map(drop);
+        }
+        if self.nodes.contains_key(new) {
+            return Err(HiveError::DuplicateNode(new.to_string()));
+        }
+        let idx = self
+            .nodes
+            .remove(old)
+            .ok_or_else(|| HiveError::UnknownNode(old.to_string()))?;
+        self.nodes.insert(new.to_string(), idx);
+        self.graph[idx].data_mut().name = new.to_string();
+        self.undo.track(UndoOp::RenameNode {
+            old: old.to_string(),
+            new: new.to_string(),
+        });
+        self.emit(Event::NodeRenamed {
+            old: old.to_string(),
+            new: new.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Rewire all edges of `other` onto `node` and remove `other` afterwards.
+    /// Edges keep their ports and attributes, whereas the attributes and ports of `other` itself are dropped.
+    /// Edges between both nodes turn into loops.
+    pub fn merge_nodes(&mut self, node: &str, other: &str) -> Result<(), HiveError> {
+        self.node(node)?;
+        let idx = self.node(other)?;
+        if node == other {
+            return Ok(());
+        }
+        let mut edges = (0..2)
+            .flat_map(|dir| self.graph.edges(idx, dir))
+            .collect::<Vec<_>>();
+        edges.sort();
+        edges.dedup();
+        self.transaction(|hive| {
+            for edge in edges {
+                let [src, dst] = hive.graph.src_dst(edge)?.map(|end| match end == idx {
+                    true => node.to_string(),
+                    false => hive.name(end).to_string(),
+                });
+                let EdgeData { ports, attrs } = hive.graph[edge].data().clone();
+                hive.remove_edge(edge)?;
+                let edge = Target::Edge(hive.add_edge(&src, &dst, ports)?.0);
+                for (key, value) in attrs {
+                    hive.set_attr(&edge, &key, Some(value))?;
                 }
-            });
-            return true;
+            }
+            hive.remove_node(other)
+        })
+    }
+
+    /// Write the whole undo history as a script of commands which rebuilds the hive state by state.
+    /// Thereby even the edge numbers and the recycling order of vacant slots are restored on load.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), HiveError> {
+        fs::write(path, self.undo.script()?)?;
+        Ok(())
+    }
+
+    /// Replace the hive by a saved one, reporting all changes to the observers.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<(), HiveError> {
+        let script = fs::read_to_string(path)?;
+        // Run the script on a scratch hive, so a broken file leaves this one untouched
+        let mut loaded = Hive::default();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorder = Rc::clone(&events);
+        loaded.subscribe(move |event| recorder.borrow_mut().push(event.clone()));
+        command::run(&mut loaded, &script)?;
+        self.clear()?;
+        // Only the observers stay, which then learn about everything the script did
+        loaded.observers = mem::take(&mut self.observers);
+        *self = loaded;
+        for event in events.take() {
+            self.emit(event);
         }
-        false
+        Ok(())
     }
 
-    fn add_edge(&mut self, src: NodeIndex, dst: NodeIndex) {
-        let edge = self.graph.add_edge(src, dst, ());
-        self.undo.track(move |hive| assert!(hive.remove_edge(edge)));
+    pub fn node_names(&self) -> impl Iterator<Item = &str> {
+        self.nodes.keys().map(String::as_str)
     }
 
-    fn remove_edge(&mut self, edge: EdgeIndex) -> bool {
-        if let Some([src, dst]) = self.graph.src_dst(edge) {
-            self.graph.remove_edge_unchecked(edge);
-            self.undo.track(move |hive| hive.add_edge(src, dst));
-            return true;
+    /// Names of all nodes in an order where every edge points forward.
+    pub fn toposort(&mut self) -> Result<Vec<&str>, HiveError> {
+        match self.graph.toposort_mut() {
+            Ok(order) => Ok(order.into_iter().map(|idx| self.name(idx)).collect()),
+            Err(cycle) => Err(self.cycle(cycle)?),
         }
-        false
+    }
+
+    /// Adjacent nodes along with the connecting edges, downstream for `dir` 0 and upstream for 1.
+    pub fn neighbors(&self, node: &str, dir: usize) -> Result<Vec<(&str, usize)>, HiveError> {
+        let idx = self.node(node)?;
+        Ok(self
+            .graph
+            .neighbors(idx, dir)
+            .map(|(neighbor, edge)| (self.name(neighbor), edge.0))
+            .collect())
+    }
+
+    /// All nodes reachable from the given one in breadth-first order, excluding itself.
+    pub fn reach(&self, node: &str, dir: usize) -> Result<Vec<&str>, HiveError> {
+        let idx = self.node(node)?;
+        Ok(self
+            .graph
+            .bfs(idx, dir)
+            .skip(1)
+            .map(|idx| self.name(idx))
+            .collect())
+    }
+
+    /// Schedule of the node as seen from every source it is reachable from.
+    pub fn schedule(&self, node: &str) -> Result<Vec<(&str, ScheduleInfo)>, HiveError> {
+        let idx = self.node(node)?;
+        let mut schedule: Vec<_> = self
+            .graph
+            .node_indices()
+            .filter(|&source| self.graph.edges(source, 1).next().is_none())
+            .filter_map(|source| {
+                let info = self.graph.schedule(source, 0).remove(&idx)?;
+                Some((self.name(source), info))
+            })
+            .collect();
+        schedule.sort_by_key(|&(source, _)| source);
+        Ok(schedule)
+    }
+
+    /// Names in each strongly connected component along with the stage of the component in the condensation.
+    /// Components are listed in topological order.
+    pub fn components(&self) -> Vec<(usize, Vec<&str>)> {
+        let condensed = self.graph.condense();
+        let stages = stages(&condensed);
+        condensed
+            .node_indices()
+            .map(|idx| {
+                let names = condensed[idx]
+                    .data()
+                    .iter()
+                    .map(|&node| self.name(node))
+                    .collect();
+                (stages[&idx], names)
+            })
+            .collect()
+    }
+
+    /// Path from `src` to `dst` with the least total `weight` attribute, where edges weigh 1 by default.
+    /// Without any numeric weight in the hive this is just the path with the fewest hops.
+    pub fn shortest_path(
+        &self,
+        src: &str,
+        dst: &str,
+    ) -> Result<Option<Vec<(&str, usize)>>, HiveError> {
+        let [src, dst] = [self.node(src)?, self.node(dst)?];
+        let mut weighted = false;
+        for edge in self.graph.edge_indices() {
+            if let Some(Value::Number(weight)) = self.graph[edge].data().attrs.get("weight") {
+                if *weight < 0.0 {
+                    return Err(HiveError::NegativeWeight(edge.0));
+                }
+                weighted = true;
+            }
+        }
+        let path = match weighted {
+            true => self
+                .graph
+                .cheapest_path(src, dst, 0, |edge| match edge.attrs.get("weight") {
+                    Some(Value::Number(weight)) => *weight,
+                    _ => 1.0,
+                })
+                .map(|(_, path)| path),
+            false => self.graph.shortest_path(src, dst, 0),
+        };
+        path.map(|path| self.hops(path)).transpose()
+    }
+
+    /// Every path from `src` to `dst` without repeated nodes and with at most `max_len` edges.
+    pub fn simple_paths(
+        &self,
+        src: &str,
+        dst: &str,
+        max_len: usize,
+    ) -> Result<Vec<Vec<(&str, usize)>>, HiveError> {
+        let [src, dst] = [self.node(src)?, self.node(dst)?];
+        self.graph
+            .all_simple_paths(src, dst, max_len)
+            .into_iter()
+            .map(|path| self.hops(path))
+            .collect()
+    }
+
+    /// Cycle rendered in pipe notation.
+    fn cycle(&self, cycle: Vec<EdgeIndex>) -> Result<HiveError, HiveError> {
+        let [src, _] = self.graph.src_dst(cycle[0])?;
+        let mut path = quote(self.name(src)).into_owned();
+        for (dst, edge) in self.hops(cycle)? {
+            write!(path, " > {}|{edge}", quote(dst)).unwrap();
+        }
+        Ok(HiveError::Cycle(path))
+    }
+
+    /// Destination and number of each edge along a path.
+    fn hops(&self, path: Vec<EdgeIndex>) -> Result<Vec<(&str, usize)>, HiveError> {
+        path.into_iter()
+            .map(|edge| {
+                let [_, dst] = self.graph.src_dst(edge)?;
+                Ok((self.name(dst), edge.0))
+            })
+            .collect()
+    }
+
+    /// Render the graph as a Graphviz digraph, optionally clustering the nodes by their stage.
+    pub fn to_dot(&self, staged: bool) -> String {
+        fn id(name: &str) -> String {
+            format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+
+        let mut dot = String::from("digraph hive {\n");
+        if staged {
+            let mut clusters: Vec<Vec<_>> = Vec::new();
+            for (idx, stage) in self.stages() {
+                if clusters.len() <= stage {
+                    clusters.resize_with(stage + 1, Vec::new);
+                }
+                clusters[stage].push(idx);
+            }
+            for (stage, cluster) in clusters.iter_mut().enumerate() {
+                cluster.sort();
+                writeln!(dot, "    subgraph cluster_{stage} {{").unwrap();
+                writeln!(dot, "        label = \"stage {stage}\";").unwrap();
+                for idx in cluster {
+                    writeln!(dot, "        {};", id(self.name(*idx))).unwrap();
+                }
+                writeln!(dot, "    }}").unwrap();
+            }
+        }
+        for idx in self.graph.node_indices() {
+            writeln!(dot, "    {};", id(self.name(idx))).unwrap();
+        }
+        for idx in self.graph.node_indices() {
+            for (neighbor, edge) in self.graph.neighbors(idx, 0) {
+                let [src, dst] = [idx, neighbor].map(|idx| id(self.name(idx)));
+                let mut label = format!("label = \"{}\"", edge.0);
+                let [src_port, dst_port] = &self.graph[edge].data().ports;
+                for (port, attr) in [(src_port, "taillabel"), (dst_port, "headlabel")] {
+                    if let Some(port) = port {
+                        write!(label, ", {attr} = {}", id(port)).unwrap();
+                    }
+                }
+                writeln!(dot, "    {src} -> {dst} [{label}];").unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Stage of every node, where the nodes of a cycle share the stage of their component in the condensation.
+    fn stages(&self) -> HashMap<NodeIndex, usize> {
+        if self.graph.toposort().is_ok() {
+            return stages(&self.graph);
+        }
+        let condensed = self.graph.condense();
+        let stages = stages(&condensed);
+        condensed
+            .node_indices()
+            .flat_map(|idx| {
+                let stage = stages[&idx];
+                condensed[idx].data().iter().map(move |&node| (node, stage))
+            })
+            .collect()
+    }
+
+    fn name(&self, idx: NodeIndex) -> &str {
+        &self.graph[idx].data().name
+    }
+
+    fn node(&self, node: &str) -> Result<NodeIndex, HiveError> {
+        self.nodes
+            .get(node)
+            .copied()
+            .ok_or_else(|| HiveError::UnknownNode(node.to_string()))
+    }
+
+    fn edge(&self, edge: usize) -> Result<EdgeIndex, HiveError> {
+        self.graph
+            .edge_index(edge)
+            .ok_or(HiveError::UnknownEdge(edge))
+    }
+
+    fn add_edge(
+        &mut self,
+        src: &str,
+        dst: &str,
+        ports: [Option<String>; 2],
+    ) -> Result<EdgeIndex, HiveError> {
+        let ends = [self.node(src)?, self.node(dst)?];
+        self.check_edge([src, dst], [0, 1].map(|dir| ports[dir].as_deref()))?;
+        let data = EdgeData {
+            ports: ports.clone(),
+            attrs: Attrs::new(),
+        };
+        let edge = self.graph.add_edge(ends[0], ends[1], data)?;
+        self.undo.track(UndoOp::AddEdge {
+            edge: edge.0,
+            src: src.to_string(),
+            dst: dst.to_string(),
+            ports: ports.clone(),
+        });
+        self.emit(Event::EdgeAdded {
+            src: src.to_string(),
+            dst: dst.to_string(),
+            ports,
+            id: edge.0,
+        });
+        Ok(edge)
+    }
+
+    fn remove_edge(&mut self, edge: EdgeIndex) -> Result<(), HiveError> {
+        self.clear_attrs(&Target::Edge(edge.0))?;
+        let [src, dst] = self.graph.src_dst(edge)?;
+        let EdgeData { ports, .. } = self.graph.remove_edge(edge)?;
+        let [src, dst] = [src, dst].map(|idx| self.name(idx).to_string());
+        self.undo.track(UndoOp::RemoveEdge {
+            edge: edge.0,
+            src: src.clone(),
+            dst: dst.clone(),
+            ports: ports.clone(),
+        });
+        self.emit(Event::EdgeRemoved {
+            src,
+            dst,
+            ports,
+            id: edge.0,
+        });
+        Ok(())
     }
 }
 
+/// Stage of each node of an acyclic graph, which is the length of the longest path reaching it.
+fn stages<N, E>(graph: &Graph<N, E>) -> HashMap<NodeIndex, usize> {
+    let mut stages = HashMap::new();
+    for idx in graph.toposort().expect("stages of a cyclic graph") {
+        let stage = graph
+            .neighbors(idx, 1)
+            .map(|(prior, _)| stages[&prior] + 1)
+            .max()
+            .unwrap_or(0);
+        stages.insert(idx, stage);
+    }
+    stages
+}
+
 impl Debug for Hive {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        const DIR_STR: [&str; 2] = [">", "<"];
         f.write_fmt(format_args!(
             "{}/{}|{}/{}|{}/{}\n",
-            self.undo.pos,
-            (self.undo.history.len() + self.undo.pos) / 2,
-            self.graph.nodes.iter().filter(|n| n.is_ok()).count(),
-            self.graph.nodes.len(),
-            self.graph.edges.iter().filter(|n| n.is_ok()).count(),
-            self.graph.edges.len(),
+            self.undo.current(),
+            self.undo.last(),
+            self.graph.node_count(),
+            self.graph.node_bound(),
+            self.graph.edge_count(),
+            self.graph.edge_bound(),
         ))?;
-        for dir in 0..1 {
-            for idx in 0..self.graph.nodes.len() {
-                if self.graph.nodes[idx].is_err() {
-                    continue;
-                }
-                let neighbors: Vec<_> = self.graph.neighbors(NodeIndex(idx), dir).collect();
-                if neighbors.is_empty() {
-                    continue;
+        for (dir, dir_str) in DIR_STR.into_iter().enumerate().take(1) {
+            for idx in self.graph.node_indices() {
+                let name = self.name(idx);
+                // Edges leaving at the same port share a line
+                for (port, neighbors) in self.ports(name, dir).unwrap() {
+                    if neighbors.is_empty() {
+                        continue;
+                    }
+                    f.write_fmt(format_args!("{} {dir_str}", end(name, port)))?;
+                    let last_idx = neighbors.len() - 1;
+                    for (idx, (node, port, edge)) in neighbors.into_iter().enumerate() {
+                        f.write_fmt(format_args!(
+                            " {}|{}{}",
+                            end(node, port),
+                            edge,
+                            if idx == last_idx { "\n" } else { "," }
+                        ))?;
+                    }
                 }
-                let idx = self
-                    .nodes
-                    .iter()
-                    .find(|(_, node)| node.0 == idx)
-                    .map(|(ident, _)| ident)
-                    .unwrap();
-                f.write_fmt(format_args!("{idx} {}", DIR_STR[dir]))?;
-                let last_idx = neighbors.len() - 1;
-                for (idx, (NodeIndex(node), EdgeIndex(edge))) in neighbors.into_iter().enumerate() {
-                    let node = self
-                        .nodes
-                        .iter()
-                        .find(|(_, idx)| node == idx.0)
-                        .map(|(ident, _)| ident)
-                        .unwrap();
-                    f.write_fmt(format_args!(
-                        " {node}|{edge}{}",
-                        if idx == last_idx { "\n" } else { "," }
-                    ))?;
+            }
+        }
+        for (name, node_type) in &self.types {
+            f.write_fmt(format_args!("type {name} {node_type}\n"))?;
+        }
+        for idx in self.graph.node_indices() {
+            let node = self.graph[idx].data();
+            if let Some(node_type) = &node.node_type {
+                f.write_fmt(format_args!("{}: {node_type}\n", quote(&node.name)))?;
+            }
+            for (dir, ports) in node.ports.iter().enumerate() {
+                if let Some(ports) = ports {
+                    let (name, ports) = (quote(&node.name), command::port_list(Some(ports)));
+                    f.write_fmt(format_args!("ports {name} {} {ports}\n", DIR_STR[dir]))?;
                 }
             }
         }
+        for idx in self.graph.node_indices() {
+            let node = self.graph[idx].data();
+            for (key, value) in &node.attrs {
+                let name = quote(&node.name);
+                f.write_fmt(format_args!("{name}.{key} = {value}\n"))?;
+            }
+        }
+        for idx in self.graph.edge_indices() {
+            for (key, value) in &self.graph[idx].data().attrs {
+                f.write_fmt(format_args!("{}.{key} = {value}\n", idx.0))?;
+            }
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hive(script: &str) -> Hive {
+        let mut hive = Hive::default();
+        command::run(&mut hive, script).unwrap();
+        hive
+    }
+
+    #[test]
+    fn stages_follow_longest_paths() {
+        let hive = hive("a > b; a > c > d > b > e\nx > y > x; y > z");
+        let stages: BTreeMap<_, _> = hive
+            .stages()
+            .into_iter()
+            .map(|(idx, stage)| (hive.name(idx), stage))
+            .collect();
+        let expected = [
+            ("a", 0),
+            ("b", 3),
+            ("c", 1),
+            ("d", 2),
+            ("e", 4),
+            ("x", 0),
+            ("y", 0),
+            ("z", 1),
+        ];
+        assert_eq!(stages, BTreeMap::from(expected));
+        let mut components: Vec<_> = hive
+            .components()
+            .into_iter()
+            .map(|(stage, mut names)| {
+                names.sort();
+                (stage, names)
+            })
+            .collect();
+        components.sort();
+        let expected = [
+            (0, vec!["a"]),
+            (0, vec!["x", "y"]),
+            (1, vec!["c"]),
+            (1, vec!["z"]),
+            (2, vec!["d"]),
+            (3, vec!["b"]),
+            (4, vec!["e"]),
+        ];
+        assert_eq!(components, expected);
+    }
+
+    #[test]
+    fn value_ignores_unrelated_nodes() {
+        let mut hive = hive("k > p; z > q; op k const 3; op p print; op q print");
+        assert_eq!(hive.value("p").unwrap(), 3.0);
+        assert!(matches!(hive.value("q"), Err(HiveError::Eval(_))));
+        assert!(hive.eval().is_err());
+    }
+
+    #[test]
+    fn failed_lines_roll_back() {
+        let expected = hive("x > y\na > b > c");
+        let mut hive = hive("x > y");
+        let last = hive.undo.last();
+        for line in ["a > b d zz", "a > b; x > b; d zz"] {
+            assert!(matches!(
+                command::run(&mut hive, line),
+                Err(HiveError::Line(1, _))
+            ));
+            let mut nodes: Vec<_> = hive.node_names().collect();
+            nodes.sort();
+            assert_eq!(nodes, ["x", "y"]);
+            assert_eq!(hive.undo.last(), last);
+        }
+        // The slots taken by the rolled back edges are handed out in the same order again
+        command::run(&mut hive, "a > b > c").unwrap();
+        assert_eq!(
+            format!("{:?}", edges(&hive)),
+            format!("{:?}", edges(&expected))
+        );
+    }
+
+    /// Downstream neighbors with their edge numbers for every node.
+    fn edges(hive: &Hive) -> Vec<(&str, Vec<(&str, usize)>)> {
+        let mut edges: Vec<_> = hive
+            .node_names()
+            .map(|name| {
+                let mut neighbors = hive.neighbors(name, 0).unwrap();
+                neighbors.sort();
+                (name, neighbors)
+            })
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    #[test]
+    fn undo_restores_exact_slots() {
+        let mut hive = hive("a > b > c; a > c; b > d");
+        let before = format!("{:?}", edges(&hive));
+        // Deleting b vacates its slots, which the new edges recycle
+        command::run(&mut hive, "delete b\ne > a\nd > c").unwrap();
+        let after = format!("{:?}", edges(&hive));
+        hive.undo(3).unwrap();
+        assert_eq!(format!("{:?}", edges(&hive)), before);
+        hive.redo(3).unwrap();
+        assert_eq!(format!("{:?}", edges(&hive)), after);
+        // A branch off an earlier state replays from the same slots as well
+        hive.undo(1).unwrap();
+        command::run(&mut hive, "c > e\ndelete 0").unwrap();
+        let branch = format!("{:?}", edges(&hive));
+
+        let path = std::env::temp_dir().join(format!("hive-undo-{}.hive", std::process::id()));
+        hive.save(&path).unwrap();
+        let mut loaded = Hive::default();
+        loaded.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(format!("{:?}", edges(&loaded)), branch);
+        hive.undo(4).unwrap();
+        loaded.undo(4).unwrap();
+        assert_eq!(format!("{:?}", edges(&hive)), before);
+        assert_eq!(format!("{:?}", edges(&loaded)), before);
+    }
+}
@@ -2,117 +2,109 @@ extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
+mod command;
+mod diagnostic;
+mod editor;
+mod eval;
+mod executor;
 mod graph;
 mod hive;
+mod undo;
 
-use std::io::Write;
+use std::{
+    env, fs,
+    io::{self, IsTerminal},
+    ops::ControlFlow,
+    path::PathBuf,
+    process::ExitCode,
+};
 
-use hive::Hive;
+use editor::HiveHelper;
+use hive::{Hive, HiveError};
+use rustyline::{error::ReadlineError, history::DefaultHistory, Editor};
 
-use pest::Parser;
+const USAGE: &str = "\
+Usage: hive                 read commands from stdin
+       hive run <script>    execute a file of commands
+       hive -c <commands>   execute the given commands";
 
-#[derive(Parser)]
-#[grammar = "hive.pest"]
-struct HiveParser;
-
-fn main() {
+fn main() -> ExitCode {
+    let args: Vec<_> = std::env::args().skip(1).collect();
     let mut hive = Hive::default();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => repl(&mut hive),
+        ["run", path] => fs::read_to_string(path)
+            .map_err(HiveError::from)
+            .and_then(|script| command::run(&mut hive, &script)),
+        ["-c", commands] => command::run(&mut hive, commands),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    // Lets clear our hive to early catch invariant violations on tear down
+    match result.and_then(|()| hive.clear()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Read commands from stdin until `quit` or end of input. Unless stdin is a terminal
+/// the prompt and dumps are left out and the first error aborts like in a script.
+fn repl(hive: &mut Hive) -> Result<(), HiveError> {
+    if io::stdin().is_terminal() {
+        return edit(hive)
+            .map_err(io::Error::other)
+            .map_err(HiveError::from);
+    }
+    for line in 1.. {
+        let at_line = |error| HiveError::Line(line, Box::new(error));
+        let mut input = String::new();
+        if io::stdin()
+            .read_line(&mut input)
+            .map_err(|error| at_line(error.into()))?
+            == 0
+        {
+            break;
+        }
+        let flow = command::execute(hive, &input).map_err(at_line)?;
+        if flow.is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Interactive session with line editing, completion and a history kept across sessions.
+fn edit(hive: &mut Hive) -> Result<(), ReadlineError> {
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".hive_history"));
+    let mut editor: Editor<HiveHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(HiveHelper::new(command::keywords())));
+    if let Some(history) = &history {
+        // There is no history yet on first use
+        let _ = editor.load_history(history);
+    }
     loop {
-        let input = {
-            print!("» ");
-            let mut line = String::new();
-            std::io::stdout().flush().unwrap();
-            std::io::stdin()
-                .read_line(&mut line)
-                .expect("Error: Could not read a line");
-            line
+        editor.helper_mut().unwrap().nodes = hive.node_names().map(str::to_string).collect();
+        let input = match editor.readline("» ") {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error),
         };
-
-        match HiveParser::parse(Rule::command, &input) {
-            Ok(mut commands) => {
-                let command = commands.next().unwrap();
-                assert_eq!(command.as_rule(), Rule::command);
-                for expr in command.into_inner() {
-                    assert_eq!(expr.as_rule(), Rule::expr);
-                    let expr = expr.into_inner().next().unwrap();
-                    match expr.as_rule() {
-                        Rule::action_seq => {
-                            let snapshot = hive.undo.snapshot();
-                            for action in expr.into_inner() {
-                                match action.as_rule() {
-                                    Rule::pipe => {
-                                        let mut pipe = action.into_inner();
-                                        let mut a = pipe.next().unwrap();
-                                        while let Some(dir) = pipe.next() {
-                                            let b = pipe.next().unwrap();
-                                            match dir.as_rule() {
-                                                Rule::to => hive.pipe(a.as_str(), b.as_str()),
-                                                Rule::from => hive.pipe(b.as_str(), a.as_str()),
-                                                _ => unreachable!(),
-                                            }
-                                            a = b;
-                                        }
-                                    }
-                                    Rule::delete => {
-                                        let delete = action.into_inner().next().unwrap();
-                                        match delete.as_rule() {
-                                            Rule::node => {
-                                                hive.remove_node(delete.as_str());
-                                            }
-                                            Rule::edge => {
-                                                hive.delete_edge(delete.as_str().parse().unwrap());
-                                            }
-                                            _ => unreachable!(),
-                                        }
-                                    }
-                                    _ => unreachable!(),
-                                }
-                            }
-                            hive.undo.pile(snapshot);
-                        }
-                        Rule::history => {
-                            let history = expr.into_inner().next().unwrap();
-                            match history.as_rule() {
-                                Rule::quit => {
-                                    // Lets clear our hive to early catch asserts on tear down
-                                    hive.clear();
-                                    return;
-                                }
-                                Rule::clear => {
-                                    hive.clear();
-                                }
-                                Rule::pile => {
-                                    let n = history
-                                        .into_inner()
-                                        .next()
-                                        .unwrap()
-                                        .as_str()
-                                        .parse()
-                                        .unwrap();
-                                    let snapshot = hive.undo.snapshot().saturating_sub(n);
-                                    hive.undo.pile(snapshot);
-                                }
-                                rule @ (Rule::undo | Rule::redo) => {
-                                    let n = history
-                                        .into_inner()
-                                        .map(|u| u.as_str().parse().unwrap())
-                                        .next()
-                                        .unwrap_or(1);
-                                    match rule {
-                                        Rule::undo => hive.undo(n),
-                                        Rule::redo => hive.redo(n),
-                                        _ => unreachable!(),
-                                    }
-                                }
-                                _ => unreachable!(),
-                            }
-                        }
-                        _ => unreachable!(),
-                    }
-                }
-                print!("{:?}", hive);
-            }
-            Err(error) => println!("{error}"),
+        editor.add_history_entry(input.as_str())?;
+        match command::execute(hive, &input) {
+            Ok(ControlFlow::Continue(())) => print!("{:?}", hive),
+            Ok(ControlFlow::Break(())) => break,
+            Err(error) => println!("Error: {error}"),
         }
     }
+    if let Some(history) = &history {
+        editor.save_history(history)?;
+    }
+    Ok(())
 }
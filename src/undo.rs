@@ -0,0 +1,316 @@
+use crate::{
+    command::{end, port_list, quote},
+    hive::{HiveError, NodeType, Target, Value, DIR_STR},
+};
+
+/// Change of the hive which can be applied as well as inverted
+#[derive(Clone, Debug)]
+pub enum UndoOp {
+    AddNode(String),
+    RemoveNode(String),
+    RenameNode {
+        old: String,
+        new: String,
+    },
+    AddEdge {
+        edge: usize,
+        src: String,
+        dst: String,
+        ports: [Option<String>; 2],
+    },
+    RemoveEdge {
+        edge: usize,
+        src: String,
+        dst: String,
+        ports: [Option<String>; 2],
+    },
+    SetAttr {
+        target: Target,
+        key: String,
+        old: Option<Value>,
+        new: Option<Value>,
+    },
+    SetPorts {
+        node: String,
+        dir: usize,
+        old: Option<Vec<String>>,
+        new: Option<Vec<String>>,
+    },
+    DefineType {
+        name: String,
+        old: Option<NodeType>,
+        new: Option<NodeType>,
+    },
+    SetType {
+        node: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    Pile(Vec<UndoOp>),
+}
+
+impl UndoOp {
+    pub fn inverse(&self) -> UndoOp {
+        match self {
+            UndoOp::AddNode(node) => UndoOp::RemoveNode(node.clone()),
+            UndoOp::RemoveNode(node) => UndoOp::AddNode(node.clone()),
+            UndoOp::RenameNode { old, new } => UndoOp::RenameNode {
+                old: new.clone(),
+                new: old.clone(),
+            },
+            UndoOp::AddEdge {
+                edge,
+                src,
+                dst,
+                ports,
+            } => UndoOp::RemoveEdge {
+                edge: *edge,
+                src: src.clone(),
+                dst: dst.clone(),
+                ports: ports.clone(),
+            },
+            UndoOp::RemoveEdge {
+                edge,
+                src,
+                dst,
+                ports,
+            } => UndoOp::AddEdge {
+                edge: *edge,
+                src: src.clone(),
+                dst: dst.clone(),
+                ports: ports.clone(),
+            },
+            UndoOp::SetAttr {
+                target,
+                key,
+                old,
+                new,
+            } => UndoOp::SetAttr {
+                target: target.clone(),
+                key: key.clone(),
+                old: new.clone(),
+                new: old.clone(),
+            },
+            UndoOp::SetPorts {
+                node,
+                dir,
+                old,
+                new,
+            } => UndoOp::SetPorts {
+                node: node.clone(),
+                dir: *dir,
+                old: new.clone(),
+                new: old.clone(),
+            },
+            UndoOp::DefineType { name, old, new } => UndoOp::DefineType {
+                name: name.clone(),
+                old: new.clone(),
+                new: old.clone(),
+            },
+            UndoOp::SetType { node, old, new } => UndoOp::SetType {
+                node: node.clone(),
+                old: new.clone(),
+                new: old.clone(),
+            },
+            UndoOp::Pile(ops) => UndoOp::Pile(ops.iter().rev().map(UndoOp::inverse).collect()),
+        }
+    }
+
+    fn flatten<'a>(&'a self, ops: &mut Vec<&'a UndoOp>) {
+        match self {
+            UndoOp::Pile(pile) => pile.iter().for_each(|op| op.flatten(ops)),
+            op => ops.push(op),
+        }
+    }
+
+    /// Render as an action sequence which causes the very same change when being executed.
+    pub fn command(&self) -> Result<String, HiveError> {
+        let mut ops = Vec::new();
+        self.flatten(&mut ops);
+        let mut actions = Vec::new();
+        // Nodes only come into existence by piping them, so they wait for their first edge
+        let mut added: Vec<&str> = Vec::new();
+        for op in ops {
+            match op {
+                UndoOp::AddNode(node) => added.push(node),
+                UndoOp::AddEdge {
+                    src,
+                    dst,
+                    ports: [src_port, dst_port],
+                    ..
+                } => {
+                    added.retain(|node| node != src && node != dst);
+                    let (src, dst) = (end(src, src_port.as_deref()), end(dst, dst_port.as_deref()));
+                    actions.push(format!("{src} > {dst}"));
+                }
+                UndoOp::RemoveEdge { edge, .. } => actions.push(format!("d {edge}")),
+                UndoOp::RemoveNode(node) => actions.push(format!("d {}", quote(node))),
+                UndoOp::RenameNode { old, new } => {
+                    actions.push(format!("rename {} {}", quote(old), quote(new)))
+                }
+                UndoOp::SetAttr {
+                    target, key, new, ..
+                } => actions.push(match new {
+                    Some(value) => format!("set {target}.{key} = {value}"),
+                    None => format!("unset {target}.{key}"),
+                }),
+                UndoOp::SetPorts { node, dir, new, .. } => actions.push(format!(
+                    "ports {} {} {}",
+                    quote(node),
+                    DIR_STR[*dir],
+                    port_list(new.as_deref())
+                )),
+                UndoOp::DefineType { name, new, .. } => actions.push(match new {
+                    Some(node_type) => format!("type {name} {node_type}"),
+                    None => format!("untype {name}"),
+                }),
+                UndoOp::SetType { node, new, .. } => {
+                    // Typing a node creates it just like piping does
+                    added.retain(|added| added != node);
+                    let node_type = new.as_deref().unwrap_or("*");
+                    actions.push(format!("{}: {node_type}", quote(node)));
+                }
+                UndoOp::Pile(_) => unreachable!(),
+            }
+        }
+        match added.first() {
+            Some(node) => Err(HiveError::InvariantViolation(format!(
+                "node `{node}` has been added without an edge"
+            ))),
+            None => Ok(actions.join(" ")),
+        }
+    }
+}
+
+/// Node of the undo tree. Ids are handed out chronologically and the root has id 0.
+pub(crate) struct State {
+    pub(crate) parent: usize,
+    /// Change leading from the parent to this state
+    pub(crate) op: UndoOp,
+    /// Child to proceed to on redo, which is the one visited most recently
+    pub(crate) next: Option<usize>,
+}
+
+pub struct Undo {
+    pub(crate) states: Vec<State>,
+    pub(crate) current: usize,
+    pub(crate) pause: bool,
+}
+
+impl Default for Undo {
+    fn default() -> Self {
+        Undo {
+            states: vec![State {
+                parent: 0,
+                op: UndoOp::Pile(Vec::new()),
+                next: None,
+            }],
+            current: 0,
+            pause: false,
+        }
+    }
+}
+
+impl Undo {
+    pub(crate) fn track(&mut self, op: UndoOp) {
+        if self.pause {
+            // FIXME: On pause we get an op just to drop it right away. Can we avoid its construction in the first place?
+            return;
+        }
+        let id = self.states.len();
+        self.states.push(State {
+            parent: self.current,
+            op,
+            next: None,
+        });
+        self.states[self.current].next = Some(id);
+        self.current = id;
+    }
+
+    pub fn snapshot(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn last(&self) -> usize {
+        self.states.len() - 1
+    }
+
+    /// Merge all states since the snapshot into a single one, given they form the current branch tip.
+    pub fn pile(&mut self, snapshot: usize) {
+        let snapshot = snapshot.max(1);
+        if self.snapshot().saturating_sub(snapshot) < 2 {
+            // Building a pile with less then 2 elements equals doing nothing here.
+            return;
+        }
+        assert!(!self.pause);
+        if self.current != self.last()
+            || (snapshot + 1..self.states.len()).any(|id| self.states[id].parent != id - 1)
+        {
+            // Piling across branches would orphan the states branching off in between
+            return;
+        }
+
+        let pile = self.states.split_off(snapshot);
+        let parent = pile[0].parent;
+        self.states.push(State {
+            parent,
+            op: UndoOp::Pile(pile.into_iter().map(|state| state.op).collect()),
+            next: None,
+        });
+        self.states[parent].next = Some(snapshot);
+        self.current = snapshot;
+    }
+
+    /// Ids along the path from the root to the given state.
+    pub(crate) fn path(&self, mut id: usize) -> Vec<usize> {
+        let mut path = vec![id];
+        while id != 0 {
+            id = self.states[id].parent;
+            path.push(id);
+        }
+        path.reverse();
+        path
+    }
+
+    /// Every path from the root to a leaf of the tree.
+    pub fn branches(&self) -> Vec<Vec<usize>> {
+        let mut leaf = vec![true; self.states.len()];
+        for state in &self.states[1..] {
+            leaf[state.parent] = false;
+        }
+        (1..self.states.len())
+            .filter(|&id| leaf[id])
+            .map(|id| self.path(id))
+            .collect()
+    }
+
+    /// Changes along the path from the root to the current state, rendered as commands.
+    pub fn history(&self) -> Result<Vec<(usize, String)>, HiveError> {
+        self.path(self.current)[1..]
+            .iter()
+            .map(|&id| Ok((id, self.states[id].op.command()?)))
+            .collect()
+    }
+
+    /// Render the whole tree as a script which rebuilds it state by state when being executed.
+    pub fn script(&self) -> Result<String, HiveError> {
+        let mut script = String::new();
+        let mut current = 0;
+        for (id, state) in self.states.iter().enumerate().skip(1) {
+            if state.parent != current {
+                script.push_str(&format!("goto {}\n", state.parent));
+            }
+            script.push_str(&state.op.command()?);
+            script.push('\n');
+            current = id;
+        }
+        if self.current != current {
+            script.push_str(&format!("goto {}\n", self.current));
+        }
+        Ok(script)
+    }
+}